@@ -0,0 +1,25 @@
+use std::fs::File;
+use std::io::BufWriter;
+
+use route_snapper_graph::{ContractionHierarchy, RouteSnapperMap};
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 3 {
+        println!("Pass in a snap.bin file and the output path, like snap.ch.bin");
+        std::process::exit(1);
+    }
+    let bytes = std::fs::read(&args[1]).unwrap();
+    let mut map: RouteSnapperMap = bincode::deserialize(&bytes).unwrap();
+    map.finalize().unwrap();
+
+    println!(
+        "Building a contraction hierarchy for {} nodes and {} edges",
+        map.nodes.len(),
+        map.edges.len()
+    );
+    let ch = ContractionHierarchy::new(&map);
+
+    let output = BufWriter::new(File::create(&args[2]).unwrap());
+    bincode::serialize_into(output, &ch).unwrap();
+}
@@ -1,4 +1,3 @@
-use geo::{line_measures::LengthMeasurable, Haversine};
 use geojson::{Feature, Geometry};
 use route_snapper_graph::RouteSnapperMap;
 
@@ -11,32 +10,7 @@ fn main() {
     let bytes = std::fs::read(&args[1]).unwrap();
     let mut map: RouteSnapperMap = bincode::deserialize(&bytes).unwrap();
 
-    // TODO Move this to route_snapper_graph
-    if !map.override_forward_costs.is_empty() && map.override_forward_costs.len() != map.edges.len()
-    {
-        panic!("override_forward_costs length doesn't match edges length",);
-    }
-    if !map.override_backward_costs.is_empty()
-        && map.override_backward_costs.len() != map.edges.len()
-    {
-        panic!("override_backward_costs length doesn't match edges length",);
-    }
-
-    for (idx, edge) in map.edges.iter_mut().enumerate() {
-        edge.length_meters = edge.geometry.length(&Haversine);
-
-        if map.override_forward_costs.is_empty() {
-            edge.forward_cost = Some(edge.length_meters);
-        } else {
-            edge.forward_cost = map.override_forward_costs[idx];
-        }
-
-        if map.override_backward_costs.is_empty() {
-            edge.backward_cost = Some(edge.length_meters);
-        } else {
-            edge.backward_cost = map.override_backward_costs[idx];
-        }
-    }
+    map.finalize().unwrap();
 
     // This is a copy of renderGraph from the WASM API. Browsers seem to have limits for how large
     // a dynamically-generated file they can download. Sharing the code for this method without
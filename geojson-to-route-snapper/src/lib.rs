@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use anyhow::{bail, Result};
 use geo::{Coord, CoordsIter, LineString};
@@ -18,6 +18,7 @@ pub fn convert_geojson(input_string: String) -> Result<RouteSnapperMap> {
         edges: Vec::new(),
         override_forward_costs: Vec::new(),
         override_backward_costs: Vec::new(),
+        localized_names: Vec::new(),
     };
 
     // Count how many lines reference each point
@@ -35,6 +36,7 @@ pub fn convert_geojson(input_string: String) -> Result<RouteSnapperMap> {
         let mut pts = Vec::new();
 
         let num_points = edge.geometry.coords_count();
+        let localized_names = edge.localized_names();
 
         for (idx, pt) in edge.geometry.into_inner().into_iter().enumerate() {
             pts.push(pt);
@@ -70,6 +72,7 @@ pub fn convert_geojson(input_string: String) -> Result<RouteSnapperMap> {
                 });
                 map.override_forward_costs.push(edge.forward_cost);
                 map.override_backward_costs.push(edge.backward_cost);
+                map.localized_names.push(localized_names.clone());
 
                 // Start the next edge
                 point1 = pt;
@@ -84,6 +87,9 @@ pub fn convert_geojson(input_string: String) -> Result<RouteSnapperMap> {
     if map.override_backward_costs.iter().all(|x| x.is_none()) {
         bail!("No edges set backward_cost. The input is probably incorrect.");
     }
+    if map.localized_names.iter().all(|names| names.is_empty()) {
+        map.localized_names.clear();
+    }
 
     Ok(map)
 }
@@ -95,6 +101,21 @@ pub struct InputEdge {
     name: Option<String>,
     forward_cost: Option<f64>,
     backward_cost: Option<f64>,
+    // Other properties, including names in other languages like `name:cy`
+    #[serde(flatten)]
+    other: HashMap<String, geojson::JsonValue>,
+}
+
+impl InputEdge {
+    fn localized_names(&self) -> BTreeMap<String, String> {
+        let mut names = BTreeMap::new();
+        for (key, value) in &self.other {
+            if let (Some(language), Some(name)) = (key.strip_prefix("name:"), value.as_str()) {
+                names.insert(language.to_string(), name.to_string());
+            }
+        }
+        names
+    }
 }
 
 fn hashify_point(pt: Coord) -> (isize, isize) {
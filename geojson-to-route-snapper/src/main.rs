@@ -13,11 +13,20 @@ struct Args {
     /// Output file to write
     #[arg(long, default_value = "snap.bin")]
     output: String,
+
+    /// Remove groups of connected roads with fewer than this many nodes. One-way roads are
+    /// respected, so dead-ends only reachable in one direction are also removed.
+    #[arg(long)]
+    min_component_size: Option<usize>,
 }
 
 fn main() {
     let args = Args::parse();
-    let snapper = convert_geojson(std::fs::read_to_string(&args.input).unwrap()).unwrap();
+    let mut snapper = convert_geojson(std::fs::read_to_string(&args.input).unwrap()).unwrap();
+    if let Some(min_size) = args.min_component_size {
+        snapper.finalize().unwrap();
+        snapper.remove_small_components(min_size);
+    }
 
     let output = BufWriter::new(File::create(args.output).unwrap());
     bincode::serialize_into(output, &snapper).unwrap();
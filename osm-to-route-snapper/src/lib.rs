@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use anyhow::Result;
 use geo::{
@@ -48,6 +48,8 @@ pub fn convert_osm(
 
 struct Way {
     name: Option<String>,
+    // From name:xx tags, keyed by the language code
+    localized_names: BTreeMap<String, String>,
     nodes: Vec<osm_reader::NodeID>,
 }
 
@@ -68,15 +70,21 @@ fn scrape_elements(
             if tags.contains_key("highway") {
                 // TODO When the name is missing, we could fallback on other OSM tags. See
                 // map_model::Road::get_name in A/B Street.
-                let name = if road_names {
-                    tags.get("name").map(|x| x.to_string())
-                } else {
-                    None
-                };
+                let mut name = None;
+                let mut localized_names = BTreeMap::new();
+                if road_names {
+                    name = tags.get("name").map(|x| x.to_string());
+                    for (key, value) in tags.iter() {
+                        if let Some(language) = key.strip_prefix("name:") {
+                            localized_names.insert(language.to_string(), value.to_string());
+                        }
+                    }
+                }
                 ways.insert(
                     id,
                     Way {
                         name,
+                        localized_names,
                         nodes: node_ids,
                     },
                 );
@@ -98,6 +106,7 @@ fn split_edges(
         edges: Vec::new(),
         override_forward_costs: Vec::new(),
         override_backward_costs: Vec::new(),
+        localized_names: Vec::new(),
     };
 
     // Count how many ways reference each node
@@ -154,6 +163,7 @@ fn split_edges(
                         forward_cost: None,
                         backward_cost: None,
                     });
+                    map.localized_names.push(way.localized_names.clone());
                 }
 
                 // Start the next edge
@@ -163,6 +173,11 @@ fn split_edges(
         }
     }
 
+    // Save space when there are no names in other languages
+    if map.localized_names.iter().all(|names| names.is_empty()) {
+        map.localized_names.clear();
+    }
+
     info!(
         "{} nodes and {} edges total",
         map.nodes.len(),
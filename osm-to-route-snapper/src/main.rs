@@ -2,6 +2,7 @@ use std::fs::File;
 use std::io::BufWriter;
 
 use clap::Parser;
+use log::info;
 use osm_to_route_snapper::convert_osm;
 
 #[derive(Parser)]
@@ -21,18 +22,29 @@ struct Args {
     /// Omit road names from the output, saving some space.
     #[clap(long)]
     no_road_names: bool,
+
+    /// Remove groups of connected roads with fewer than this many nodes, such as isolated car
+    /// parks. One-way roads are respected, so dead-ends only reachable in one direction are also
+    /// removed.
+    #[arg(long)]
+    min_component_size: Option<usize>,
 }
 
 fn main() {
     simple_logger::init_with_level(log::Level::Info).unwrap();
     let args = Args::parse();
-    let snapper = convert_osm(
+    let mut snapper = convert_osm(
         std::fs::read(&args.input).unwrap(),
         args.boundary
             .map(|path| std::fs::read_to_string(path).unwrap()),
         !args.no_road_names,
     )
     .unwrap();
+    if let Some(min_size) = args.min_component_size {
+        snapper.finalize().unwrap();
+        let removed = snapper.remove_small_components(min_size);
+        info!("Removed {removed} nodes in components smaller than {min_size}");
+    }
 
     let output = BufWriter::new(File::create(args.output).unwrap());
     bincode::serialize_into(output, &snapper).unwrap();
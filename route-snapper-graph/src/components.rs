@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{EdgeID, NodeID, RouteSnapperMap};
+
+/// Identifies a group of nodes that can all reach each other. Components are numbered by
+/// decreasing size, so the largest is always 0.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct ComponentID(pub u32);
+
+impl RouteSnapperMap {
+    /// Labels every node with its strongly connected component, using the finalized costs. Within
+    /// one component, there's a route from any node to any other, respecting one-way edges.
+    pub fn strongly_connected_components(&self) -> Vec<ComponentID> {
+        self.strongly_connected_components_with(|e, forwards| {
+            let edge = self.edge(e);
+            if forwards {
+                edge.forward_cost.is_some()
+            } else {
+                edge.backward_cost.is_some()
+            }
+        })
+    }
+
+    /// Like `strongly_connected_components`, but `usable` decides if an edge can be crossed in
+    /// each direction. `true` means forwards, from `node1` to `node2`.
+    pub fn strongly_connected_components_with(
+        &self,
+        usable: impl Fn(EdgeID, bool) -> bool,
+    ) -> Vec<ComponentID> {
+        let mut outgoing: Vec<Vec<usize>> = vec![Vec::new(); self.nodes.len()];
+        let mut incoming: Vec<Vec<usize>> = vec![Vec::new(); self.nodes.len()];
+        for (idx, edge) in self.edges.iter().enumerate() {
+            let (n1, n2) = (edge.node1.0 as usize, edge.node2.0 as usize);
+            if usable(EdgeID(idx as u32), true) {
+                outgoing[n1].push(n2);
+                incoming[n2].push(n1);
+            }
+            if usable(EdgeID(idx as u32), false) {
+                outgoing[n2].push(n1);
+                incoming[n1].push(n2);
+            }
+        }
+
+        // Kosaraju's algorithm, without recursion to handle large graphs. First record the order
+        // nodes finish in a depth-first search following edges forwards.
+        let mut visited = vec![false; self.nodes.len()];
+        let mut finished = Vec::with_capacity(self.nodes.len());
+        for start in 0..self.nodes.len() {
+            if visited[start] {
+                continue;
+            }
+            visited[start] = true;
+            let mut stack = vec![(start, 0)];
+            while let Some((node, next_idx)) = stack.pop() {
+                if let Some(next) = outgoing[node].get(next_idx).cloned() {
+                    stack.push((node, next_idx + 1));
+                    if !visited[next] {
+                        visited[next] = true;
+                        stack.push((next, 0));
+                    }
+                } else {
+                    finished.push(node);
+                }
+            }
+        }
+
+        // Then in reverse finishing order, everything reachable backwards is one component
+        let mut component: Vec<Option<usize>> = vec![None; self.nodes.len()];
+        let mut sizes = Vec::new();
+        for start in finished.into_iter().rev() {
+            if component[start].is_some() {
+                continue;
+            }
+            let id = sizes.len();
+            let mut size = 0;
+            component[start] = Some(id);
+            let mut stack = vec![start];
+            while let Some(node) = stack.pop() {
+                size += 1;
+                for next in &incoming[node] {
+                    if component[*next].is_none() {
+                        component[*next] = Some(id);
+                        stack.push(*next);
+                    }
+                }
+            }
+            sizes.push(size);
+        }
+
+        // Renumber by size, breaking ties by the order found
+        let mut order: Vec<usize> = (0..sizes.len()).collect();
+        order.sort_by_key(|id| (std::cmp::Reverse(sizes[*id]), *id));
+        let mut renumber = vec![0; sizes.len()];
+        for (new_id, old_id) in order.into_iter().enumerate() {
+            renumber[old_id] = new_id as u32;
+        }
+        component
+            .into_iter()
+            .map(|id| ComponentID(renumber[id.unwrap()]))
+            .collect()
+    }
+
+    /// Removes every strongly connected component with fewer than `min_size` nodes, along with
+    /// any edge touching them. Remaining nodes are renumbered. This must be called after
+    /// `finalize`. Returns the number of nodes removed.
+    pub fn remove_small_components(&mut self, min_size: usize) -> usize {
+        let components = self.strongly_connected_components();
+        let mut sizes = Vec::new();
+        for id in &components {
+            let idx = id.0 as usize;
+            if sizes.len() <= idx {
+                sizes.resize(idx + 1, 0);
+            }
+            sizes[idx] += 1;
+        }
+
+        let mut new_ids: Vec<Option<NodeID>> = Vec::with_capacity(self.nodes.len());
+        let mut nodes = Vec::new();
+        for (pt, id) in self.nodes.iter().zip(&components) {
+            if sizes[id.0 as usize] >= min_size {
+                new_ids.push(Some(NodeID(nodes.len() as u32)));
+                nodes.push(*pt);
+            } else {
+                new_ids.push(None);
+            }
+        }
+        let removed = self.nodes.len() - nodes.len();
+        self.nodes = nodes;
+
+        let keep: Vec<bool> = self
+            .edges
+            .iter()
+            .map(|edge| {
+                new_ids[edge.node1.0 as usize].is_some() && new_ids[edge.node2.0 as usize].is_some()
+            })
+            .collect();
+        let mut idx = 0;
+        self.edges.retain_mut(|edge| {
+            idx += 1;
+            if !keep[idx - 1] {
+                return false;
+            }
+            edge.node1 = new_ids[edge.node1.0 as usize].unwrap();
+            edge.node2 = new_ids[edge.node2.0 as usize].unwrap();
+            true
+        });
+        for costs in [
+            &mut self.override_forward_costs,
+            &mut self.override_backward_costs,
+        ] {
+            if !costs.is_empty() {
+                let mut keep = keep.iter();
+                costs.retain(|_| *keep.next().unwrap());
+            }
+        }
+        if !self.localized_names.is_empty() {
+            let mut keep = keep.iter();
+            self.localized_names.retain(|_| *keep.next().unwrap());
+        }
+
+        removed
+    }
+}
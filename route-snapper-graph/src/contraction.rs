@@ -0,0 +1,388 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{EdgeID, NodeID, RouteSnapperMap};
+
+// When checking if a shortcut is needed, give up searching for a witness path after this many
+// nodes. Giving up early just adds an unnecessary shortcut; it doesn't break correctness.
+const MAX_WITNESS_SETTLED: usize = 500;
+
+/// A contraction hierarchy speeds up pathfinding on large graphs. It's built once from a
+/// finalized `RouteSnapperMap`, using the `forward_cost` and `backward_cost` of every edge, and
+/// stored in a separate file next to the graph.
+#[derive(Serialize, Deserialize)]
+pub struct ContractionHierarchy {
+    // Used to detect a hierarchy built from a different graph
+    num_nodes: u32,
+    num_edges: u32,
+
+    /// The order each node was contracted in
+    rank: Vec<u32>,
+    arcs: Vec<Arc>,
+
+    /// Indices into `arcs` leaving each node and going to a higher rank. Calculated from `arcs`
+    /// and `rank`. Don't serialize to minimize file sizes.
+    #[serde(skip_serializing, skip_deserializing)]
+    upwards_out: Vec<Vec<u32>>,
+    /// Indices into `arcs` arriving at each node and coming from a higher rank.
+    #[serde(skip_serializing, skip_deserializing)]
+    upwards_in: Vec<Vec<u32>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Arc {
+    from: NodeID,
+    to: NodeID,
+    cost: f64,
+    via: Via,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Via {
+    /// An edge from the original graph, crossed forwards or backwards
+    Edge(EdgeID, bool),
+    /// A shortcut over a contracted node, made of two other arcs
+    Shortcut(u32, u32),
+}
+
+impl ContractionHierarchy {
+    /// Builds a hierarchy from a finalized map. This is slow for large graphs, so it's meant to
+    /// be run once as a preprocessing step, not in the browser.
+    pub fn new(map: &RouteSnapperMap) -> Self {
+        let mut builder = Builder {
+            arcs: Vec::new(),
+            outgoing: vec![BTreeMap::new(); map.nodes.len()],
+            incoming: vec![BTreeMap::new(); map.nodes.len()],
+        };
+        for (idx, edge) in map.edges.iter().enumerate() {
+            let id = EdgeID(idx as u32);
+            for (from, to, cost, forwards) in [
+                (edge.node1, edge.node2, edge.forward_cost, true),
+                (edge.node2, edge.node1, edge.backward_cost, false),
+            ] {
+                // Loops can never be part of a shortest path
+                if let (Some(cost), true) = (cost, from != to) {
+                    builder.add_arc(Arc {
+                        from,
+                        to,
+                        cost,
+                        via: Via::Edge(id, forwards),
+                    });
+                }
+            }
+        }
+
+        // Contract the node that adds the fewest shortcuts first. Priorities change as neighbors
+        // are contracted, so lazily recalculate them.
+        let mut contracted_neighbors = vec![0; map.nodes.len()];
+        let mut queue = BinaryHeap::new();
+        for node in 0..map.nodes.len() as u32 {
+            queue.push(Reverse((builder.priority(node, 0), node)));
+        }
+
+        let mut rank = vec![0; map.nodes.len()];
+        let mut next_rank = 0;
+        while let Some(Reverse((_, node))) = queue.pop() {
+            let priority = builder.priority(node, contracted_neighbors[node as usize]);
+            if let Some(Reverse((next_priority, _))) = queue.peek() {
+                if priority > *next_priority {
+                    queue.push(Reverse((priority, node)));
+                    continue;
+                }
+            }
+
+            for neighbor in builder.contract(node) {
+                contracted_neighbors[neighbor as usize] += 1;
+            }
+            rank[node as usize] = next_rank;
+            next_rank += 1;
+        }
+
+        let mut ch = Self {
+            num_nodes: map.nodes.len() as u32,
+            num_edges: map.edges.len() as u32,
+            rank,
+            arcs: builder.arcs,
+            upwards_out: Vec::new(),
+            upwards_in: Vec::new(),
+        };
+        ch.finalize(map).unwrap();
+        ch
+    }
+
+    /// After deserializing, check this hierarchy matches the map and prepare it for queries.
+    pub fn finalize(&mut self, map: &RouteSnapperMap) -> Result<(), String> {
+        if self.num_nodes as usize != map.nodes.len() || self.num_edges as usize != map.edges.len()
+        {
+            return Err("The contraction hierarchy was built from a different graph".to_string());
+        }
+
+        self.upwards_out = vec![Vec::new(); map.nodes.len()];
+        self.upwards_in = vec![Vec::new(); map.nodes.len()];
+        for (idx, arc) in self.arcs.iter().enumerate() {
+            let (from, to) = (arc.from.0 as usize, arc.to.0 as usize);
+            if self.rank[from] < self.rank[to] {
+                self.upwards_out[from].push(idx as u32);
+            } else {
+                self.upwards_in[to].push(idx as u32);
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds the cheapest path between two nodes, returning the sequence of edges and whether
+    /// each one is crossed forwards.
+    pub fn pathfind(&self, node1: NodeID, node2: NodeID) -> Option<Vec<(EdgeID, bool)>> {
+        if node1 == node2 {
+            return Some(Vec::new());
+        }
+
+        // Search upwards from both ends at once. For each node reached, remember the cost and the
+        // arc used to get there.
+        let mut forwards: HashMap<u32, (f64, Option<u32>)> = HashMap::new();
+        let mut backwards: HashMap<u32, (f64, Option<u32>)> = HashMap::new();
+        let mut forwards_queue = BinaryHeap::new();
+        let mut backwards_queue = BinaryHeap::new();
+        forwards.insert(node1.0, (0.0, None));
+        backwards.insert(node2.0, (0.0, None));
+        forwards_queue.push(Item {
+            cost: 0.0,
+            node: node1.0,
+        });
+        backwards_queue.push(Item {
+            cost: 0.0,
+            node: node2.0,
+        });
+
+        let mut best: Option<(f64, u32)> = None;
+        loop {
+            let best_cost = best.map(|(cost, _)| cost).unwrap_or(f64::INFINITY);
+            let forwards_next = forwards_queue
+                .peek()
+                .map(|x| x.cost)
+                .filter(|cost| *cost < best_cost);
+            let backwards_next = backwards_queue
+                .peek()
+                .map(|x| x.cost)
+                .filter(|cost| *cost < best_cost);
+
+            let is_forwards = match (forwards_next, backwards_next) {
+                (None, None) => break,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (Some(f), Some(b)) => f <= b,
+            };
+            let (queue, dist, other_dist, arcs) = if is_forwards {
+                (
+                    &mut forwards_queue,
+                    &mut forwards,
+                    &backwards,
+                    &self.upwards_out,
+                )
+            } else {
+                (
+                    &mut backwards_queue,
+                    &mut backwards,
+                    &forwards,
+                    &self.upwards_in,
+                )
+            };
+
+            let Item { cost, node } = queue.pop().unwrap();
+            if cost > dist[&node].0 {
+                continue;
+            }
+            if let Some((other_cost, _)) = other_dist.get(&node) {
+                if cost + other_cost < best_cost {
+                    best = Some((cost + other_cost, node));
+                }
+            }
+
+            for arc_idx in &arcs[node as usize] {
+                let arc = &self.arcs[*arc_idx as usize];
+                let next = if is_forwards { arc.to.0 } else { arc.from.0 };
+                let next_cost = cost + arc.cost;
+                if dist.get(&next).map(|(x, _)| next_cost < *x).unwrap_or(true) {
+                    dist.insert(next, (next_cost, Some(*arc_idx)));
+                    queue.push(Item {
+                        cost: next_cost,
+                        node: next,
+                    });
+                }
+            }
+        }
+
+        let (_, meeting_node) = best?;
+        let mut path_arcs = Vec::new();
+        let mut current = meeting_node;
+        while let Some(arc_idx) = forwards[&current].1 {
+            path_arcs.push(arc_idx);
+            current = self.arcs[arc_idx as usize].from.0;
+        }
+        path_arcs.reverse();
+        current = meeting_node;
+        while let Some(arc_idx) = backwards[&current].1 {
+            path_arcs.push(arc_idx);
+            current = self.arcs[arc_idx as usize].to.0;
+        }
+
+        let mut edges = Vec::new();
+        for arc_idx in path_arcs {
+            self.unpack(arc_idx, &mut edges);
+        }
+        Some(edges)
+    }
+
+    fn unpack(&self, arc_idx: u32, edges: &mut Vec<(EdgeID, bool)>) {
+        match self.arcs[arc_idx as usize].via {
+            Via::Edge(id, forwards) => edges.push((id, forwards)),
+            Via::Shortcut(arc1, arc2) => {
+                self.unpack(arc1, edges);
+                self.unpack(arc2, edges);
+            }
+        }
+    }
+}
+
+struct Builder {
+    arcs: Vec<Arc>,
+    // For every node, the arcs to and from uncontracted neighbors, keyed by that neighbor. Only
+    // the cheapest arc between two nodes is kept.
+    outgoing: Vec<BTreeMap<u32, u32>>,
+    incoming: Vec<BTreeMap<u32, u32>>,
+}
+
+impl Builder {
+    fn add_arc(&mut self, arc: Arc) {
+        let (from, to) = (arc.from.0, arc.to.0);
+        if let Some(existing) = self.outgoing[from as usize].get(&to) {
+            if self.arcs[*existing as usize].cost <= arc.cost {
+                return;
+            }
+        }
+        let idx = self.arcs.len() as u32;
+        self.arcs.push(arc);
+        self.outgoing[from as usize].insert(to, idx);
+        self.incoming[to as usize].insert(from, idx);
+    }
+
+    // Lower is better: the number of shortcuts contracting this node would add, minus the arcs
+    // it removes. Prefer nodes whose neighbors haven't been contracted, to spread out the work.
+    fn priority(&self, node: u32, contracted_neighbors: i64) -> i64 {
+        let removed = self.incoming[node as usize].len() + self.outgoing[node as usize].len();
+        self.shortcuts_needed(node).len() as i64 - removed as i64 + contracted_neighbors
+    }
+
+    // Returns (from, to, cost, first arc, second arc) for every shortcut needed to preserve
+    // shortest paths through this node after it's contracted
+    fn shortcuts_needed(&self, node: u32) -> Vec<(u32, u32, f64, u32, u32)> {
+        let mut shortcuts = Vec::new();
+        for (from, arc1) in &self.incoming[node as usize] {
+            let cost1 = self.arcs[*arc1 as usize].cost;
+            let targets: Vec<(u32, f64, u32)> = self.outgoing[node as usize]
+                .iter()
+                .filter(|(to, _)| *to != from)
+                .map(|(to, arc2)| (*to, cost1 + self.arcs[*arc2 as usize].cost, *arc2))
+                .collect();
+            let Some(max_cost) = targets.iter().map(|(_, cost, _)| *cost).reduce(f64::max) else {
+                continue;
+            };
+
+            let witnesses = self.witness_search(*from, node, max_cost);
+            for (to, cost, arc2) in targets {
+                if witnesses.get(&to).map(|x| *x > cost).unwrap_or(true) {
+                    shortcuts.push((*from, to, cost, *arc1, arc2));
+                }
+            }
+        }
+        shortcuts
+    }
+
+    // Dijkstra from `start` through uncontracted nodes, never visiting `skip`
+    fn witness_search(&self, start: u32, skip: u32, max_cost: f64) -> HashMap<u32, f64> {
+        let mut dist = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        dist.insert(start, 0.0);
+        queue.push(Item {
+            cost: 0.0,
+            node: start,
+        });
+
+        let mut settled = 0;
+        while let Some(Item { cost, node }) = queue.pop() {
+            if cost > dist[&node] {
+                continue;
+            }
+            settled += 1;
+            if cost > max_cost || settled > MAX_WITNESS_SETTLED {
+                break;
+            }
+            for (next, arc) in &self.outgoing[node as usize] {
+                if *next == skip {
+                    continue;
+                }
+                let next_cost = cost + self.arcs[*arc as usize].cost;
+                if dist.get(next).map(|x| next_cost < *x).unwrap_or(true) {
+                    dist.insert(*next, next_cost);
+                    queue.push(Item {
+                        cost: next_cost,
+                        node: *next,
+                    });
+                }
+            }
+        }
+        dist
+    }
+
+    // Removes the node from the remaining graph, adds shortcuts, and returns the neighbors
+    fn contract(&mut self, node: u32) -> Vec<u32> {
+        for (from, to, cost, arc1, arc2) in self.shortcuts_needed(node) {
+            self.add_arc(Arc {
+                from: NodeID(from),
+                to: NodeID(to),
+                cost,
+                via: Via::Shortcut(arc1, arc2),
+            });
+        }
+
+        let incoming = std::mem::take(&mut self.incoming[node as usize]);
+        let outgoing = std::mem::take(&mut self.outgoing[node as usize]);
+        for from in incoming.keys() {
+            self.outgoing[*from as usize].remove(&node);
+        }
+        for to in outgoing.keys() {
+            self.incoming[*to as usize].remove(&node);
+        }
+
+        let mut neighbors: Vec<u32> = incoming.into_keys().chain(outgoing.into_keys()).collect();
+        neighbors.sort();
+        neighbors.dedup();
+        neighbors
+    }
+}
+
+// Orders by lowest cost first, for use in a BinaryHeap
+#[derive(PartialEq)]
+struct Item {
+    cost: f64,
+    node: u32,
+}
+
+impl Eq for Item {}
+
+impl PartialOrd for Item {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Item {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| self.node.cmp(&other.node))
+    }
+}
@@ -0,0 +1,136 @@
+use geo::{Bearing, Coord, Haversine, Point};
+use serde::{Deserialize, Serialize};
+
+use crate::{EdgeID, RouteSnapperMap};
+
+/// How sharply a path turns between two edges
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Turn {
+    Straight,
+    SlightLeft,
+    Left,
+    SharpLeft,
+    SlightRight,
+    Right,
+    SharpRight,
+    UTurn,
+}
+
+/// One step of directions along a path, following one street
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Step {
+    /// The name of the street followed for this step
+    pub name: Option<String>,
+    /// How to turn onto this street. `None` for the first step.
+    pub turn: Option<Turn>,
+    /// The angle turned onto this street, in degrees. Positive is to the right (clockwise), and
+    /// it's 0 for the first step.
+    pub turn_degrees: f64,
+    /// Where the step starts
+    pub start: Coord,
+    pub length_meters: f64,
+    /// The edges followed, and whether each one is crossed forwards (from `node1` to `node2`)
+    pub edges: Vec<(EdgeID, bool)>,
+}
+
+impl Turn {
+    /// Classifies an angle in degrees, positive to the right, between -180 and 180
+    pub fn from_degrees(degrees: f64) -> Self {
+        let magnitude = degrees.abs();
+        if magnitude < 20.0 {
+            Turn::Straight
+        } else if magnitude >= 160.0 {
+            Turn::UTurn
+        } else if degrees < 0.0 {
+            if magnitude < 60.0 {
+                Turn::SlightLeft
+            } else if magnitude < 120.0 {
+                Turn::Left
+            } else {
+                Turn::SharpLeft
+            }
+        } else if magnitude < 60.0 {
+            Turn::SlightRight
+        } else if magnitude < 120.0 {
+            Turn::Right
+        } else {
+            Turn::SharpRight
+        }
+    }
+}
+
+impl RouteSnapperMap {
+    /// Describes a path through the graph as steps, one per street. The path is a sequence of
+    /// edges, each crossed forwards (`true`) or backwards, like `ContractionHierarchy::pathfind`
+    /// returns. Consecutive edges with the same name are merged, unless the path makes a U-turn.
+    /// Consecutive unnamed edges are merged only when the path continues straight. Names are in
+    /// the given language when available.
+    pub fn directions(&self, path: &[(EdgeID, bool)], language: Option<&str>) -> Vec<Step> {
+        let mut steps: Vec<Step> = Vec::new();
+        for (id, forwards) in path {
+            let edge = self.edge(*id);
+            let name = self.edge_name(*id, language).map(|x| x.to_string());
+            let pts = self.directed_points(*id, *forwards);
+
+            if let Some(step) = steps.last_mut() {
+                let (prev_id, prev_forwards) = *step.edges.last().unwrap();
+                let prev_pts = self.directed_points(prev_id, prev_forwards);
+                let degrees = turn_degrees(&prev_pts, &pts);
+                let turn = Turn::from_degrees(degrees);
+                let merge = turn != Turn::UTurn
+                    && step.name == name
+                    && (name.is_some() || turn == Turn::Straight);
+                if merge {
+                    step.length_meters += edge.length_meters;
+                    step.edges.push((*id, *forwards));
+                    continue;
+                }
+                steps.push(Step {
+                    name,
+                    turn: Some(turn),
+                    turn_degrees: degrees,
+                    start: pts[0],
+                    length_meters: edge.length_meters,
+                    edges: vec![(*id, *forwards)],
+                });
+            } else {
+                steps.push(Step {
+                    name,
+                    turn: None,
+                    turn_degrees: 0.0,
+                    start: pts[0],
+                    length_meters: edge.length_meters,
+                    edges: vec![(*id, *forwards)],
+                });
+            }
+        }
+        steps
+    }
+
+    // The edge's points in the order they're crossed
+    fn directed_points(&self, id: EdgeID, forwards: bool) -> Vec<Coord> {
+        let mut pts = self.edge(id).geometry.0.clone();
+        if !forwards {
+            pts.reverse();
+        }
+        pts
+    }
+}
+
+// The change in bearing from the end of one line to the start of the next, between -180 and 180
+fn turn_degrees(incoming: &[Coord], outgoing: &[Coord]) -> f64 {
+    let end = incoming[incoming.len() - 1];
+    let before = incoming.iter().rev().find(|pt| **pt != end).unwrap_or(&end);
+    let start = outgoing[0];
+    let after = outgoing.iter().find(|pt| **pt != start).unwrap_or(&start);
+
+    let bearing1 = Haversine.bearing(Point::from(*before), Point::from(end));
+    let bearing2 = Haversine.bearing(Point::from(start), Point::from(*after));
+    let degrees = (bearing2 - bearing1).rem_euclid(360.0);
+    if degrees > 180.0 {
+        degrees - 360.0
+    } else {
+        degrees
+    }
+}
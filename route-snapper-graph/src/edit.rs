@@ -0,0 +1,337 @@
+use std::collections::BTreeMap;
+
+use geo::{
+    line_measures::LengthMeasurable, Closest, ClosestPoint, Coord, Distance, Haversine, Line,
+    LineString, Point,
+};
+use rstar::primitives::GeomWithData;
+use rstar::RTree;
+use serde::{Deserialize, Serialize};
+
+use crate::{Edge, EdgeID, NodeID, RouteSnapperMap};
+
+// When re-applying a patch, endpoints were already resolved to exact positions, so only allow for
+// floating point error
+const PATCH_SNAP_METERS: f64 = 0.1;
+
+// Roughly the length of a degree of latitude
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+/// Every node, for finding the closest one to a point
+pub type NodeRTree = RTree<GeomWithData<[f64; 2], NodeID>>;
+/// Every segment of every edge, for finding the closest edge to a point
+pub type EdgeSegmentRTree = RTree<GeomWithData<rstar::primitives::Line<[f64; 2]>, EdgeID>>;
+
+/// A list of edits to a graph. It can be saved and re-applied to a fresh copy of the same graph.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct GraphPatch {
+    pub new_edges: Vec<NewEdge>,
+}
+
+/// A new edge to add to a graph. Each endpoint is connected to an existing node, splits an
+/// existing edge, or becomes a new node.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct NewEdge {
+    /// [longitude, latitude] pairs
+    pub geometry: Vec<[f64; 2]>,
+    pub name: Option<String>,
+    /// If a cost is `None`, the edge won't be routable in that direction.
+    pub forward_cost: Option<f64>,
+    pub backward_cost: Option<f64>,
+}
+
+/// Describes how `add_edge` changed a map, so callers can update anything derived from it.
+pub struct GraphChanges {
+    pub new_nodes: Vec<NodeID>,
+    pub split_edges: Vec<SplitEdge>,
+    pub new_edge: EdgeID,
+    /// The input, with endpoints moved to exactly where they were connected. Applying this to a
+    /// fresh copy of the original graph will reproduce the same changes.
+    pub resolved: NewEdge,
+}
+
+/// An existing edge was split in two. It keeps its ID and `node1`, and now ends at a new node.
+/// The rest of it becomes `new_edge`, which ends at `old_node2`.
+pub struct SplitEdge {
+    pub edge: EdgeID,
+    pub new_edge: EdgeID,
+    pub old_node2: NodeID,
+    /// The share of the original edge's cost kept by `edge`. The rest goes to `new_edge`.
+    pub fraction: f64,
+}
+
+impl GraphPatch {
+    /// Applies every edit to a finalized map, keeping its spatial indices up-to-date.
+    pub fn apply(
+        &self,
+        map: &mut RouteSnapperMap,
+        nodes: &mut NodeRTree,
+        edge_segments: &mut EdgeSegmentRTree,
+    ) -> Result<Vec<GraphChanges>, String> {
+        // Check before changing anything
+        for new_edge in &self.new_edges {
+            new_edge.check_costs()?;
+        }
+        let mut changes = Vec::new();
+        for new_edge in &self.new_edges {
+            changes.push(map.add_edge(new_edge, PATCH_SNAP_METERS, nodes, edge_segments)?);
+        }
+        Ok(changes)
+    }
+}
+
+impl NewEdge {
+    fn check_costs(&self) -> Result<(), String> {
+        for cost in [self.forward_cost, self.backward_cost]
+            .into_iter()
+            .flatten()
+        {
+            if !cost.is_finite() || cost < 0.0 {
+                return Err("Edge costs must be non-negative".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RouteSnapperMap {
+    /// Builds the index used by `add_edge` to find the closest edge to a point.
+    pub fn index_edge_segments(&self) -> EdgeSegmentRTree {
+        RTree::bulk_load(
+            (0..self.edges.len())
+                .flat_map(|idx| self.segments(EdgeID(idx as u32)))
+                .collect(),
+        )
+    }
+
+    /// Adds a new edge to a finalized map. Endpoints within `snap_meters` of an existing node
+    /// connect to it. Otherwise, endpoints within that distance of an existing edge split it. The
+    /// indices of `nodes` and `edge_segments` are used to find these, and are updated to match.
+    pub fn add_edge(
+        &mut self,
+        new_edge: &NewEdge,
+        snap_meters: f64,
+        nodes: &mut NodeRTree,
+        edge_segments: &mut EdgeSegmentRTree,
+    ) -> Result<GraphChanges, String> {
+        new_edge.check_costs()?;
+        let mut pts: Vec<Coord> = new_edge.geometry.iter().map(|x| (*x).into()).collect();
+        pts.dedup();
+        if pts.len() < 2 {
+            return Err("A new edge needs at least two distinct points".to_string());
+        }
+
+        let mut new_nodes = Vec::new();
+        let mut split_edges = Vec::new();
+        let mut endpoints = Vec::new();
+        for idx in [0, pts.len() - 1] {
+            let (node, is_new, split) =
+                self.connect_point(pts[idx], snap_meters, nodes, edge_segments);
+            if is_new {
+                new_nodes.push(node);
+            }
+            split_edges.extend(split);
+            pts[idx] = self.node(node);
+            endpoints.push(node);
+        }
+        if endpoints[0] == endpoints[1] && pts.len() < 3 {
+            return Err("A new edge can't start and end at the same place".to_string());
+        }
+
+        let geometry = LineString::new(pts);
+        let length_meters = geometry.length(&Haversine);
+        let id = self.push_edge(Edge {
+            node1: endpoints[0],
+            node2: endpoints[1],
+            geometry,
+            name: new_edge.name.clone(),
+            length_meters,
+            forward_cost: new_edge.forward_cost,
+            backward_cost: new_edge.backward_cost,
+        });
+        for segment in self.segments(id) {
+            edge_segments.insert(segment);
+        }
+
+        let mut resolved = new_edge.clone();
+        resolved.geometry = self
+            .edge(id)
+            .geometry
+            .coords()
+            .map(|pt| [pt.x, pt.y])
+            .collect();
+        Ok(GraphChanges {
+            new_nodes,
+            split_edges,
+            new_edge: id,
+            resolved,
+        })
+    }
+
+    // Finds or creates the node to use for a point, possibly splitting an existing edge. Returns
+    // the node, whether it was just created, and any edge that was split.
+    fn connect_point(
+        &mut self,
+        pt: Coord,
+        snap_meters: f64,
+        nodes: &mut NodeRTree,
+        edge_segments: &mut EdgeSegmentRTree,
+    ) -> (NodeID, bool, Option<SplitEdge>) {
+        let point = Point::from(pt);
+        // Searching in degrees, anything this far away is definitely too far in meters
+        let max_degrees = snap_meters / (METERS_PER_DEGREE * pt.y.to_radians().cos());
+
+        let mut closest_node: Option<(NodeID, f64)> = None;
+        for (obj, dist_squared) in nodes.nearest_neighbor_iter_with_distance_2(&[pt.x, pt.y]) {
+            if dist_squared.sqrt() > max_degrees {
+                break;
+            }
+            let dist = Haversine.distance(point, Point::from(*obj.geom()));
+            if dist <= snap_meters && closest_node.map(|x| dist < x.1).unwrap_or(true) {
+                closest_node = Some((obj.data, dist));
+            }
+        }
+        if let Some((node, _)) = closest_node {
+            return (node, false, None);
+        }
+
+        // Find the closest point on any edge, remembering the segment
+        let mut closest_edge: Option<(EdgeID, Line, Coord, f64)> = None;
+        for (obj, dist_squared) in
+            edge_segments.nearest_neighbor_iter_with_distance_2(&[pt.x, pt.y])
+        {
+            if dist_squared.sqrt() > max_degrees {
+                break;
+            }
+            let line = Line::new(obj.geom().from, obj.geom().to);
+            let on_line = match line.closest_point(&point) {
+                Closest::Intersection(x) | Closest::SinglePoint(x) => x.into(),
+                Closest::Indeterminate => continue,
+            };
+            let dist = Haversine.distance(point, Point::from(on_line));
+            if dist <= snap_meters && closest_edge.map(|x| dist < x.3).unwrap_or(true) {
+                closest_edge = Some((obj.data, line, on_line, dist));
+            }
+        }
+
+        let node = NodeID(self.nodes.len() as u32);
+        let Some((edge, line, on_line, _)) = closest_edge else {
+            self.nodes.push(pt);
+            nodes.insert(GeomWithData::new([pt.x, pt.y], node));
+            return (node, true, None);
+        };
+        self.nodes.push(on_line);
+        nodes.insert(GeomWithData::new([on_line.x, on_line.y], node));
+
+        let line_idx = self
+            .edge(edge)
+            .geometry
+            .lines()
+            .position(|x| x == line)
+            .unwrap();
+        for segment in self.segments(edge) {
+            edge_segments.remove(&segment);
+        }
+        let split = self.split_edge(edge, line_idx, node);
+        for id in [split.edge, split.new_edge] {
+            for segment in self.segments(id) {
+                edge_segments.insert(segment);
+            }
+        }
+        (node, true, Some(split))
+    }
+
+    // Splits an edge at a new node, which must lie on the given segment of its geometry. Costs are
+    // divided by length.
+    fn split_edge(&mut self, id: EdgeID, line_idx: usize, node: NodeID) -> SplitEdge {
+        let split_pt = self.node(node);
+        let edge = &mut self.edges[id.0 as usize];
+        let old_node2 = edge.node2;
+
+        let mut pts1 = edge.geometry.0[..=line_idx].to_vec();
+        pts1.push(split_pt);
+        pts1.dedup();
+        let mut pts2 = vec![split_pt];
+        pts2.extend(edge.geometry.0[line_idx + 1..].iter().cloned());
+        pts2.dedup();
+
+        let old_length = edge.length_meters;
+        edge.geometry = LineString::new(pts1);
+        edge.node2 = node;
+        edge.length_meters = edge.geometry.length(&Haversine);
+        let geometry2 = LineString::new(pts2);
+        let length2 = geometry2.length(&Haversine);
+
+        // Avoid dividing by zero for degenerate edges
+        let fraction1 = if old_length > 0.0 {
+            edge.length_meters / old_length
+        } else {
+            0.5
+        };
+        let fraction2 = 1.0 - fraction1;
+        let forward_cost = edge.forward_cost;
+        let backward_cost = edge.backward_cost;
+        edge.forward_cost = forward_cost.map(|x| x * fraction1);
+        edge.backward_cost = backward_cost.map(|x| x * fraction1);
+        let name = edge.name.clone();
+
+        let idx = id.0 as usize;
+        if !self.override_forward_costs.is_empty() {
+            let cost = self.override_forward_costs[idx];
+            self.override_forward_costs[idx] = cost.map(|x| x * fraction1);
+        }
+        if !self.override_backward_costs.is_empty() {
+            let cost = self.override_backward_costs[idx];
+            self.override_backward_costs[idx] = cost.map(|x| x * fraction1);
+        }
+
+        let new_edge = self.push_edge(Edge {
+            node1: node,
+            node2: old_node2,
+            geometry: geometry2,
+            name,
+            length_meters: length2,
+            forward_cost: forward_cost.map(|x| x * fraction2),
+            backward_cost: backward_cost.map(|x| x * fraction2),
+        });
+        if !self.localized_names.is_empty() {
+            self.localized_names[new_edge.0 as usize] = self.localized_names[idx].clone();
+        }
+
+        SplitEdge {
+            edge: id,
+            new_edge,
+            old_node2,
+            fraction: fraction1,
+        }
+    }
+
+    // Every segment of an edge, as stored in an EdgeSegmentRTree
+    fn segments(
+        &self,
+        id: EdgeID,
+    ) -> impl Iterator<Item = GeomWithData<rstar::primitives::Line<[f64; 2]>, EdgeID>> + '_ {
+        self.edge(id).geometry.lines().map(move |line| {
+            GeomWithData::new(
+                rstar::primitives::Line::new(line.start.into(), line.end.into()),
+                id,
+            )
+        })
+    }
+
+    // Adds a finalized edge, keeping the override costs and localized names in sync
+    fn push_edge(&mut self, edge: Edge) -> EdgeID {
+        let id = EdgeID(self.edges.len() as u32);
+        if !self.override_forward_costs.is_empty() {
+            self.override_forward_costs.push(edge.forward_cost);
+        }
+        if !self.override_backward_costs.is_empty() {
+            self.override_backward_costs.push(edge.backward_cost);
+        }
+        if !self.localized_names.is_empty() {
+            self.localized_names.push(BTreeMap::new());
+        }
+        self.edges.push(edge);
+        id
+    }
+}
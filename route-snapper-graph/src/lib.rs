@@ -1,6 +1,18 @@
-use geo::{Coord, LineString};
+use std::collections::BTreeMap;
+
+use geo::{line_measures::LengthMeasurable, Coord, Haversine, LineString};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+pub use components::ComponentID;
+pub use contraction::ContractionHierarchy;
+pub use directions::{Step, Turn};
+pub use edit::{EdgeSegmentRTree, GraphChanges, GraphPatch, NewEdge, NodeRTree, SplitEdge};
+
+mod components;
+mod contraction;
+mod directions;
+mod edit;
+
 #[derive(Serialize, Deserialize)]
 pub struct RouteSnapperMap {
     #[serde(
@@ -15,6 +27,12 @@ pub struct RouteSnapperMap {
     /// `None`, that edge won't be routable in the specified direction.
     pub override_forward_costs: Vec<Option<f64>>,
     pub override_backward_costs: Vec<Option<f64>>,
+
+    /// Optional names of edges in other languages, keyed by a language code like `cy`, from
+    /// OpenStreetMap `name:cy` tags. If non-empty, this must match the length of `edges`. Files
+    /// from before this existed have none.
+    #[serde(default, deserialize_with = "deserialize_or_default")]
+    pub localized_names: Vec<BTreeMap<String, String>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -51,6 +69,55 @@ impl RouteSnapperMap {
     pub fn node(&self, id: NodeID) -> Coord {
         self.nodes[id.0 as usize]
     }
+
+    /// The name of an edge, in the given language if it has a name in that language
+    pub fn edge_name(&self, id: EdgeID, language: Option<&str>) -> Option<&str> {
+        if let Some(language) = language {
+            if let Some(name) = self
+                .localized_names
+                .get(id.0 as usize)
+                .and_then(|names| names.get(language))
+            {
+                return Some(name);
+            }
+        }
+        self.edge(id).name.as_deref()
+    }
+
+    /// After deserializing, fill out `length_meters`, `forward_cost`, and `backward_cost` for
+    /// every edge. Fails if the override costs don't match the edges.
+    pub fn finalize(&mut self) -> Result<(), String> {
+        if !self.override_forward_costs.is_empty()
+            && self.override_forward_costs.len() != self.edges.len()
+        {
+            return Err("override_forward_costs length doesn't match edges length".to_string());
+        }
+        if !self.override_backward_costs.is_empty()
+            && self.override_backward_costs.len() != self.edges.len()
+        {
+            return Err("override_backward_costs length doesn't match edges length".to_string());
+        }
+        if !self.localized_names.is_empty() && self.localized_names.len() != self.edges.len() {
+            return Err("localized_names length doesn't match edges length".to_string());
+        }
+
+        for (idx, edge) in self.edges.iter_mut().enumerate() {
+            edge.length_meters = edge.geometry.length(&Haversine);
+
+            if self.override_forward_costs.is_empty() {
+                edge.forward_cost = Some(edge.length_meters);
+            } else {
+                edge.forward_cost = self.override_forward_costs[idx];
+            }
+
+            if self.override_backward_costs.is_empty() {
+                edge.backward_cost = Some(edge.length_meters);
+            } else {
+                edge.backward_cost = self.override_backward_costs[idx];
+            }
+        }
+        Ok(())
+    }
 }
 
 fn serialize_coords<S: Serializer>(coords: &Vec<Coord>, s: S) -> Result<S::Ok, S::Error> {
@@ -83,6 +150,25 @@ fn deserialize_linestring<'de, D: Deserializer<'de>>(d: D) -> Result<LineString,
     Ok(LineString::new(pts))
 }
 
+// This is the last field of a bincode file, which doesn't record field names. Older files end
+// before it, so treat running out of input as the field being absent. Any other error is real.
+fn deserialize_or_default<'de, D: Deserializer<'de>, T: Deserialize<'de> + Default>(
+    d: D,
+) -> Result<T, D::Error> {
+    match T::deserialize(d) {
+        Ok(x) => Ok(x),
+        Err(err) if is_end_of_input(&err) => Ok(T::default()),
+        Err(err) => Err(err),
+    }
+}
+
+// bincode reports running out of input as an I/O error, but the error type is generic here and
+// doesn't expose its source, so it can only be recognized by the message
+fn is_end_of_input<E: std::fmt::Display>(err: &E) -> bool {
+    let eof = std::io::Error::from(std::io::ErrorKind::UnexpectedEof);
+    err.to_string().ends_with(&eof.to_string())
+}
+
 /// Serializes a trimmed `f64` as an `i32` to save space.
 fn serialize_f64(x: f64) -> i32 {
     // 6 decimal places gives about 10cm of precision
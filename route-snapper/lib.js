@@ -55,10 +55,77 @@ export class RouteSnapper {
           "circle-opacity": ["case", ["has", "hovered"], 0.5, 1.0],
         },
       });
+      this.map.addLayer({
+        id: "route-alternatives",
+        source: "route-snapper",
+        filter: ["all", ["in", "$type", "LineString"], ["has", "alternative"]],
+        type: "line",
+        layout: {
+          "line-cap": "round",
+          "line-join": "round",
+        },
+        paint: {
+          "line-color": "grey",
+          "line-width": 4,
+          "line-opacity": 0.6,
+        },
+      });
+      this.map.addLayer({
+        id: "route-inactive",
+        source: "route-snapper",
+        filter: ["all", ["in", "$type", "LineString"], ["has", "inactive"]],
+        type: "line",
+        layout: {
+          "line-cap": "round",
+          "line-join": "round",
+        },
+        paint: {
+          "line-color": "purple",
+          "line-width": 2.5,
+          "line-opacity": 0.6,
+          "line-dasharray": [2, 1],
+        },
+      });
+      this.map.addLayer({
+        id: "route-invalid",
+        source: "route-snapper",
+        filter: ["all", ["in", "$type", "LineString"], ["has", "invalid"]],
+        type: "line",
+        layout: {
+          "line-cap": "round",
+          "line-join": "round",
+        },
+        paint: {
+          "line-color": "orange",
+          "line-width": 8,
+          "line-opacity": 0.7,
+        },
+      });
+      this.map.addLayer({
+        id: "route-disconnected",
+        source: "route-snapper",
+        filter: ["all", ["in", "$type", "LineString"], ["has", "disconnected"]],
+        type: "line",
+        layout: {
+          "line-cap": "round",
+        },
+        paint: {
+          "line-color": "black",
+          "line-width": 4,
+          "line-dasharray": [1, 2],
+        },
+      });
       this.map.addLayer({
         id: "route-lines",
         source: "route-snapper",
-        filter: ["in", "$type", "LineString"],
+        filter: [
+          "all",
+          ["in", "$type", "LineString"],
+          ["!has", "alternative"],
+          ["!has", "inactive"],
+          ["!has", "invalid"],
+          ["!has", "disconnected"],
+        ],
         type: "line",
         layout: {
           "line-cap": "round",
@@ -76,7 +143,7 @@ export class RouteSnapper {
         type: "fill",
         paint: {
           "fill-color": "black",
-          "fill-opacity": 0.4,
+          "fill-opacity": ["case", ["has", "inactive"], 0.15, 0.4],
         },
       });
       this.loaded = true;
@@ -96,11 +163,25 @@ export class RouteSnapper {
         }
       });
 
-      this.map.on("click", () => {
+      this.map.on("click", (e) => {
         if (!this.active) {
           return;
         }
-        this.inner.onClick();
+        let pickBlocks = document.getElementById("pickBlocks");
+        if (pickBlocks && pickBlocks.checked) {
+          // Shift-click merges with the current area
+          try {
+            this.inner.areaFromPoint(
+              e.lngLat.lng,
+              e.lngLat.lat,
+              e.originalEvent.shiftKey
+            );
+          } catch (err) {
+            console.warn(`Couldn't pick a block: ${err}`);
+          }
+        } else {
+          this.inner.onClick();
+        }
         this.#redraw();
       });
 
@@ -143,6 +224,10 @@ export class RouteSnapper {
           e.preventDefault();
           this.inner.toggleSnapMode();
           this.#redraw();
+        } else if (e.ctrlKey && (e.key == "y" || e.key == "Z")) {
+          e.preventDefault();
+          this.inner.redo();
+          this.#redraw();
         } else if (e.key == "z" && e.ctrlKey) {
           e.preventDefault();
           this.inner.undo();
@@ -161,6 +246,14 @@ export class RouteSnapper {
     console.timeEnd("Deserialize and setup JsRouteSnapper with new graph");
   }
 
+  // Speed up pathfinding on large graphs, using a file built by the
+  // contract-graph tool from the same graph.
+  loadContractionHierarchy(chBytes) {
+    console.time("Deserialize contraction hierarchy");
+    this.inner.loadContractionHierarchy(chBytes);
+    console.timeEnd("Deserialize contraction hierarchy");
+  }
+
   isActive() {
     return this.active;
   }
@@ -173,6 +266,8 @@ export class RouteSnapper {
       return;
     }
     this.map.removeLayer("route-points");
+    this.map.removeLayer("route-alternatives");
+    this.map.removeLayer("route-inactive");
     this.map.removeLayer("route-lines");
     this.map.removeSource("route-snapper");
     // TODO Remove the event listeners on document and map
@@ -216,11 +311,90 @@ export class RouteSnapper {
 
     this.start();
 
-    if (feature.geometry.type == "Polygon") {
+    if (
+      feature.geometry.type == "Polygon" ||
+      feature.geometry.type == "MultiPolygon"
+    ) {
       this.inner.setAreaMode();
     }
 
-    this.inner.editExisting(feature.properties.waypoints);
+    this.inner.editExisting(
+      feature.properties.waypoints,
+      feature.properties.other_rings
+    );
+    this.#redraw();
+  }
+
+  // Restarts the tool with the route points from a GPX file. Points within
+  // snapDistanceMeters of the network snap to it; the rest are freehand.
+  importGpx(gpx, snapDistanceMeters = 5) {
+    if (!this.loaded) {
+      console.error("importGpx called before the map idle event received");
+      return;
+    }
+    this.start();
+    this.inner.importGpx(gpx, snapDistanceMeters);
+    this.#redraw();
+  }
+
+  // Returns the current route as a GPX string, or null if it isn't finished.
+  toGpx() {
+    return this.inner.toGpx() || null;
+  }
+
+  // Exports finished routes as "geojson", "polyline5", "polyline6", "kml",
+  // "flatgeobuf", or "gpx". FlatGeobuf is returned as a Uint8Array, and everything else
+  // as a string. Only the active route is exported, unless allRoutes is true.
+  exportRoutes(format, allRoutes = false) {
+    let bytes = this.inner.exportRoutes(format, allRoutes);
+    return format == "flatgeobuf" ? bytes : new TextDecoder().decode(bytes);
+  }
+
+  // Restarts the tool with a route map-matched from a GPS trace, given as a GPX
+  // string with a track or a GeoJSON LineString. gpsAccuracyMeters is the
+  // typical error of the trace. Returns an object describing the match quality.
+  matchTrace(trace, gpsAccuracyMeters = 10) {
+    if (!this.loaded) {
+      console.error("matchTrace called before the map idle event received");
+      return null;
+    }
+    this.start();
+    let input = typeof trace == "string" ? trace : JSON.stringify(trace);
+    let quality = JSON.parse(this.inner.matchTrace(input, gpsAccuracyMeters));
+    this.#redraw();
+    return quality;
+  }
+
+  // Returns the entire editing state, including undo history, to be saved and
+  // later passed to importSession.
+  exportSession() {
+    return JSON.parse(this.inner.exportSession());
+  }
+
+  // Restart the tool with state from exportSession. The same graph must be
+  // loaded.
+  importSession(session) {
+    if (!this.loaded) {
+      console.error(
+        "importSession called before the map idle event received. Not starting tool."
+      );
+      return;
+    }
+
+    this.start();
+    this.inner.importSession(JSON.stringify(session));
+
+    let config = JSON.parse(this.inner.getConfig());
+    for (let [id, value] of [
+      ["avoidDoublingBack", config.avoid_doubling_back],
+      ["extendRoute", config.extend_route],
+      ["areaMode", config.area_mode],
+    ]) {
+      let checkbox = document.getElementById(id);
+      if (checkbox) {
+        checkbox.checked = value;
+      }
+    }
     this.#redraw();
   }
 
@@ -255,6 +429,7 @@ export class RouteSnapper {
     <div style="display: flex; justify-content: space-evenly;">
       <button type="button" id="finish-route-button">Finish route</button>
       <button type="button" id="undo-button" disabled>Undo</button>
+      <button type="button" id="redo-button" disabled>Redo</button>
       <button type="button" id="cancel-button">Cancel</button>
     </div>
 
@@ -276,6 +451,12 @@ export class RouteSnapper {
         Area mode
       </label>
     </div>
+    <div>
+      <label>
+        <input type="checkbox" id="pickBlocks" />
+        Click inside blocks (area mode, shift-click to merge)
+      </label>
+    </div>
 
     <div id="snap_mode" style="background: red; color: white; padding: 8px">
       Snapping to transport network
@@ -287,9 +468,9 @@ export class RouteSnapper {
     <ul>
       <li><b>Click</b> green points on the transport network</br>to create snapped routes</li>
       <li>Press <b>s</b> to toggle snapping / freehand mode</li>
-      <li><b>Click and drag</b> any point to move it</li>
+      <li><b>Click and drag</b> any point to move it, or the route to add a point</li>
       <li><b>Click</b> a red waypoint to delete it</li>
-      <li>Press <b>Control+Z</b> to undo</li>
+      <li>Press <b>Control+Z</b> to undo and <b>Control+Y</b> to redo</li>
       <li>Press <b>Enter</b> or <b>double click</b> to finish route</li>
       <li>Press <b>Escape</b> to cancel and discard route</li>
     </ul>
@@ -307,6 +488,10 @@ export class RouteSnapper {
       this.inner.undo();
       this.#redraw();
     };
+    document.getElementById("redo-button").onclick = () => {
+      this.inner.redo();
+      this.#redraw();
+    };
     document.getElementById("cancel-button").onclick = () => {
       this.controlDiv.dispatchEvent(new CustomEvent("no-new-route"));
       this.stop();
@@ -363,11 +548,123 @@ export class RouteSnapper {
     return this.inner.debugRenderGraph();
   }
 
+  // Set zones to avoid or penalize, as a GeoJSON FeatureCollection. See the
+  // user guide for details.
+  setZones(gj) {
+    this.inner.setZones(JSON.stringify(gj));
+    this.#redraw();
+  }
+
+  // Override the cost of crossing an edge in one direction. If cost is
+  // undefined, close the edge in that direction.
+  setEdgeCost(edgeId, forwards, cost) {
+    this.inner.setEdgeCost(edgeId, forwards, cost);
+    this.#redraw();
+  }
+
+  // Close an edge in both directions.
+  closeEdge(edgeId) {
+    this.inner.closeEdge(edgeId);
+    this.#redraw();
+  }
+
+  // Remove any cost overrides or closures for an edge.
+  restoreEdge(edgeId) {
+    this.inner.restoreEdge(edgeId);
+    this.#redraw();
+  }
+
+  // Add a new edge to the graph from a GeoJSON LineString Feature, with
+  // optional name, forward_cost, and backward_cost properties. Each end
+  // connects to an existing node or edge within snapDistanceMeters. Returns the
+  // new edge ID.
+  addEdge(gj, snapDistanceMeters) {
+    let id = this.inner.addEdge(JSON.stringify(gj), snapDistanceMeters);
+    this.#redraw();
+    return id;
+  }
+
+  // Make a different waypoint the first and last point of a closed area,
+  // without changing the polygon.
+  rotateArea(waypointIdx) {
+    this.inner.rotateArea(waypointIdx);
+    this.#redraw();
+  }
+
+  // In area mode, make the area the block of streets around a point. If merge
+  // is true, add the block to the current area instead.
+  areaFromPoint(lon, lat, merge) {
+    this.inner.areaFromPoint(lon, lat, merge);
+    this.#redraw();
+  }
+
+  // When enabled, snapped waypoints that no path can reach aren't added.
+  setRefuseDisconnected(enabled) {
+    this.inner.setRefuseDisconnected(enabled);
+  }
+
+  // When enabled, new waypoints only snap to nodes in the same connected
+  // component as the last snapped waypoint.
+  setSnapWithinComponent(enabled) {
+    this.inner.setSnapWithinComponent(enabled);
+  }
+
+  // When enabled, invalid areas are fixed before being returned, by removing
+  // spikes and resolving self-intersections.
+  setRepairAreas(enabled) {
+    this.inner.setRepairAreas(enabled);
+    this.#redraw();
+  }
+
+  // In area mode, finish the current ring and start drawing another one, to
+  // make a hole or a separate part.
+  startNewRing() {
+    this.inner.startNewRing();
+    this.#redraw();
+  }
+
+  // Returns all edges added so far, to be saved and passed to applyGraphPatch
+  // later.
+  exportGraphPatch() {
+    return JSON.parse(this.inner.exportGraphPatch());
+  }
+
+  // Re-apply edges from exportGraphPatch to the same original graph.
+  applyGraphPatch(patch) {
+    this.inner.applyGraphPatch(JSON.stringify(patch));
+    this.#redraw();
+  }
+
+  // Preview up to k alternative routes for the leg starting at waypoint index
+  // leg. Returns the number found.
+  previewAlternatives(leg, k) {
+    let num = this.inner.previewAlternatives(leg, k);
+    this.#redraw();
+    return num;
+  }
+
+  // Change the route to follow one of the previewed alternatives.
+  pinAlternative(idx) {
+    this.inner.pinAlternative(idx);
+    this.#redraw();
+  }
+
   // Given waypoint properties, calculate the route name.
   routeNameForWaypoints(waypoints) {
     return this.inner.routeNameForWaypoints(waypoints);
   }
 
+  // Changes the text used for route, area, and waypoint names. See the user
+  // guide for the fields.
+  setNaming(naming) {
+    this.inner.setNaming(JSON.stringify(naming));
+  }
+
+  // Returns a list of turn-by-turn directions for the current route.
+  getDirections() {
+    return JSON.parse(this.inner.getDirections());
+  }
+
   #finishSnapping() {
     // Update the source-of-truth in drawControls
     const rawJSON = this.inner.toFinalFeature();
@@ -397,6 +694,16 @@ export class RouteSnapper {
           undoButton.disabled = true;
         }
       }
+      let redoButton = document.getElementById("redo-button");
+      if (redoButton) {
+        if (gj.redo_length > 0) {
+          redoButton.disabled = false;
+          redoButton.textContent = `Redo (${gj.redo_length})`;
+        } else {
+          redoButton.textContent = "Redo";
+          redoButton.disabled = true;
+        }
+      }
 
       // TODO Detect changes, don't do this constantly?
       let snapDiv = document.getElementById("snap_mode");
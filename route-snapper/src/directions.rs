@@ -0,0 +1,191 @@
+use geo::{Coord, Distance, Haversine, Point};
+use route_snapper_graph::{EdgeID, RouteSnapperMap, Step, Turn};
+use serde::Serialize;
+
+use crate::{trim_lon_lat, PathEntry};
+
+/// One step of turn-by-turn directions
+#[derive(Serialize)]
+pub struct JsonStep {
+    /// `depart`, `continue` after a straight line, a turn like `slight_left` or `u_turn`,
+    /// `straight_line` for parts not on the network, or `arrive`
+    pub maneuver: &'static str,
+    pub name: Option<String>,
+    pub length_meters: f64,
+    /// Positive is to the right
+    pub turn_degrees: f64,
+    /// [longitude, latitude] where the step begins
+    pub point: [f64; 2],
+    pub instruction: String,
+}
+
+// Part of a route, either following the network or drawn freehand
+enum Section {
+    Streets(Vec<(EdgeID, bool)>),
+    StraightLine(Vec<Coord>),
+}
+
+/// Describes a route's path as written directions. Parts following the network have one step per
+/// street, and parts drawn as straight lines have one step each. Street names are in the given
+/// language when available.
+pub fn directions(
+    map: &RouteSnapperMap,
+    full_path: &[PathEntry],
+    language: Option<&str>,
+) -> Vec<JsonStep> {
+    let mut steps = Vec::new();
+    let mut last_pt = None;
+    for section in sections(map, full_path) {
+        match section {
+            Section::Streets(edges) => {
+                for step in map.directions(&edges, language) {
+                    let maneuver = match step.turn {
+                        Some(turn) => turn_maneuver(turn),
+                        None if steps.is_empty() => "depart",
+                        None => "continue",
+                    };
+                    let instruction = instruction(&step);
+                    steps.push(JsonStep {
+                        maneuver,
+                        name: step.name,
+                        length_meters: step.length_meters,
+                        turn_degrees: step.turn_degrees,
+                        point: [trim_lon_lat(step.start.x), trim_lon_lat(step.start.y)],
+                        instruction,
+                    });
+                }
+                let (id, forwards) = *edges.last().unwrap();
+                let edge = map.edge(id);
+                last_pt = Some(map.node(if forwards { edge.node2 } else { edge.node1 }));
+            }
+            Section::StraightLine(pts) => {
+                let length_meters = pts
+                    .windows(2)
+                    .map(|pair| Haversine.distance(Point::from(pair[0]), Point::from(pair[1])))
+                    .sum();
+                steps.push(JsonStep {
+                    maneuver: "straight_line",
+                    name: None,
+                    length_meters,
+                    turn_degrees: 0.0,
+                    point: [trim_lon_lat(pts[0].x), trim_lon_lat(pts[0].y)],
+                    instruction: format!(
+                        "Go in a straight line for {}",
+                        format_distance(length_meters)
+                    ),
+                });
+                last_pt = pts.last().cloned();
+            }
+        }
+    }
+
+    if let Some(pt) = last_pt {
+        steps.push(JsonStep {
+            maneuver: "arrive",
+            name: None,
+            length_meters: 0.0,
+            turn_degrees: 0.0,
+            point: [trim_lon_lat(pt.x), trim_lon_lat(pt.y)],
+            instruction: "Arrive".to_string(),
+        });
+    }
+    steps
+}
+
+// Splits the path into runs of edges and straight lines. Waypoints in the middle of a run of
+// edges don't break it up.
+fn sections(map: &RouteSnapperMap, full_path: &[PathEntry]) -> Vec<Section> {
+    let mut sections = Vec::new();
+    let mut edges = Vec::new();
+    let mut line: Vec<Coord> = Vec::new();
+    // The previous point, if it wasn't followed by an edge
+    let mut prev_pt: Option<Coord> = None;
+    for entry in full_path {
+        let pt = match entry {
+            PathEntry::Edge(dir_edge) => {
+                if line.len() >= 2 {
+                    sections.push(Section::StraightLine(std::mem::take(&mut line)));
+                }
+                line.clear();
+                edges.push((dir_edge.0, dir_edge.1));
+                prev_pt = None;
+                continue;
+            }
+            PathEntry::SnappedPoint(node) => map.node(*node),
+            PathEntry::FreePoint(pt) => *pt,
+        };
+        // Two points in a row are joined by a straight line
+        if let Some(prev) = prev_pt {
+            if prev != pt {
+                if !edges.is_empty() {
+                    sections.push(Section::Streets(std::mem::take(&mut edges)));
+                }
+                if line.is_empty() {
+                    line.push(prev);
+                }
+                line.push(pt);
+            }
+        }
+        prev_pt = Some(pt);
+    }
+    if !edges.is_empty() {
+        sections.push(Section::Streets(edges));
+    }
+    if line.len() >= 2 {
+        sections.push(Section::StraightLine(line));
+    }
+    sections
+}
+
+fn turn_maneuver(turn: Turn) -> &'static str {
+    match turn {
+        Turn::Straight => "straight",
+        Turn::SlightLeft => "slight_left",
+        Turn::Left => "left",
+        Turn::SharpLeft => "sharp_left",
+        Turn::SlightRight => "slight_right",
+        Turn::Right => "right",
+        Turn::SharpRight => "sharp_right",
+        Turn::UTurn => "u_turn",
+    }
+}
+
+fn instruction(step: &Step) -> String {
+    let distance = format_distance(step.length_meters);
+    let verb = match step.turn {
+        None => {
+            return match &step.name {
+                Some(name) => format!("Continue on {name} for {distance}"),
+                None => format!("Continue for {distance}"),
+            };
+        }
+        Some(Turn::Straight) => {
+            return match &step.name {
+                Some(name) => format!("Continue onto {name} for {distance}"),
+                None => format!("Continue straight for {distance}"),
+            };
+        }
+        Some(Turn::SlightLeft) => "Bear left",
+        Some(Turn::Left) => "Turn left",
+        Some(Turn::SharpLeft) => "Turn sharp left",
+        Some(Turn::SlightRight) => "Bear right",
+        Some(Turn::Right) => "Turn right",
+        Some(Turn::SharpRight) => "Turn sharp right",
+        Some(Turn::UTurn) => "Make a U-turn",
+    };
+    match &step.name {
+        Some(name) => format!("{verb} onto {name} and continue for {distance}"),
+        None => format!("{verb} and continue for {distance}"),
+    }
+}
+
+// Rounds to a sensible precision for reading
+fn format_distance(meters: f64) -> String {
+    if meters >= 1000.0 {
+        format!("{:.1} km", meters / 1000.0)
+    } else if meters >= 100.0 {
+        format!("{} m", (meters / 10.0).round() * 10.0)
+    } else {
+        format!("{} m", meters.round())
+    }
+}
@@ -0,0 +1,278 @@
+//! Writes finished routes and areas in different formats. Every format starts from the same
+//! `RouteResult`, so they all keep the same properties.
+
+use std::fmt::Write;
+
+use geo::{Coord, Geometry, LineString, Polygon};
+use geojson::{Feature, FeatureCollection};
+
+use crate::gpx::{escape, GpxPoint, GpxRoute};
+
+/// A finished route or area, with everything any format needs
+pub struct RouteResult {
+    pub name: String,
+    /// A LineString for a route, or a Polygon or MultiPolygon for an area
+    pub geometry: Geometry,
+    /// The waypoints of the route, or of the first ring of an area
+    pub waypoints: Vec<ResultWaypoint>,
+    /// Everything else describing the route, like `length_meters` and `waypoints`. These are the
+    /// properties of the GeoJSON output.
+    pub properties: serde_json::Map<String, serde_json::Value>,
+}
+
+pub struct ResultWaypoint {
+    pub pt: Coord,
+    pub name: String,
+    pub snapped: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    GeoJson,
+    /// Google encoded polylines, with this many decimal places
+    Polyline(u32),
+    Kml,
+    FlatGeobuf,
+    Gpx,
+}
+
+impl Format {
+    pub fn parse(format: &str) -> Result<Format, String> {
+        match format {
+            "geojson" => Ok(Format::GeoJson),
+            "polyline5" => Ok(Format::Polyline(5)),
+            "polyline6" => Ok(Format::Polyline(6)),
+            "kml" => Ok(Format::Kml),
+            "flatgeobuf" => Ok(Format::FlatGeobuf),
+            "gpx" => Ok(Format::Gpx),
+            _ => Err(format!("Unknown export format {format}")),
+        }
+    }
+}
+
+/// Writes any number of routes in one format. Everything except FlatGeobuf is text.
+pub fn write(format: Format, results: &[RouteResult]) -> Vec<u8> {
+    match format {
+        Format::GeoJson => {
+            let fc: FeatureCollection = results.iter().map(to_feature).collect();
+            serde_json::to_string_pretty(&fc).unwrap().into_bytes()
+        }
+        Format::Polyline(precision) => to_polylines(results, precision).into_bytes(),
+        Format::Kml => to_kml(results).into_bytes(),
+        Format::FlatGeobuf => crate::flatgeobuf::write(results),
+        Format::Gpx => to_gpx(results).into_bytes(),
+    }
+}
+
+pub fn to_feature(result: &RouteResult) -> Feature {
+    let mut f = Feature::from(geojson::Geometry::from(&result.geometry));
+    f.properties = Some(result.properties.clone());
+    f
+}
+
+/// Writes a JSON array with one object per route, holding all of its properties, plus `polyline`
+/// and `precision`. For a route, `polyline` is one encoded string. For a Polygon, it's a list of
+/// rings, exterior first, and for a MultiPolygon, a list of those.
+pub fn to_polylines(results: &[RouteResult], precision: u32) -> String {
+    let mut output = Vec::new();
+    for result in results {
+        let encoded = match &result.geometry {
+            Geometry::LineString(line) => encode_polyline(&line.0, precision).into(),
+            Geometry::Polygon(polygon) => encode_polygon(polygon, precision),
+            Geometry::MultiPolygon(mp) => mp
+                .iter()
+                .map(|polygon| encode_polygon(polygon, precision))
+                .collect(),
+            _ => serde_json::Value::Null,
+        };
+        let mut object = result.properties.clone();
+        object.insert("polyline".to_string(), encoded);
+        object.insert("precision".to_string(), precision.into());
+        output.push(serde_json::Value::Object(object));
+    }
+    serde_json::to_string_pretty(&output).unwrap()
+}
+
+fn encode_polygon(polygon: &Polygon, precision: u32) -> serde_json::Value {
+    std::iter::once(polygon.exterior())
+        .chain(polygon.interiors())
+        .map(|ring| serde_json::Value::from(encode_polyline(&ring.0, precision)))
+        .collect()
+}
+
+/// Encodes points with Google's polyline algorithm, rounding to `precision` decimal places.
+pub fn encode_polyline(pts: &[Coord], precision: u32) -> String {
+    let factor = 10_f64.powi(precision as i32);
+    let mut output = String::new();
+    let mut prev = (0, 0);
+    for pt in pts {
+        // Latitude comes first
+        let next = (
+            (pt.y * factor).round() as i64,
+            (pt.x * factor).round() as i64,
+        );
+        encode_value(next.0 - prev.0, &mut output);
+        encode_value(next.1 - prev.1, &mut output);
+        prev = next;
+    }
+    output
+}
+
+fn encode_value(value: i64, output: &mut String) {
+    let mut value = if value < 0 { !(value << 1) } else { value << 1 };
+    while value >= 0x20 {
+        output.push(char::from((((value & 0x1f) | 0x20) + 63) as u8));
+        value >>= 5;
+    }
+    output.push(char::from((value + 63) as u8));
+}
+
+/// Writes a KML document with a folder per route. Each folder has a placemark for the route,
+/// with the properties as extended data, and a named placemark for every waypoint.
+pub fn to_kml(results: &[RouteResult]) -> String {
+    let mut out = String::new();
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(out, r#"<kml xmlns="http://www.opengis.net/kml/2.2">"#).unwrap();
+    writeln!(out, "  <Document>").unwrap();
+    for result in results {
+        writeln!(out, "    <Folder>").unwrap();
+        writeln!(out, "      <name>{}</name>", escape(&result.name)).unwrap();
+
+        writeln!(out, "      <Placemark>").unwrap();
+        writeln!(out, "        <name>{}</name>", escape(&result.name)).unwrap();
+        writeln!(out, "        <ExtendedData>").unwrap();
+        for (key, value) in &result.properties {
+            writeln!(
+                out,
+                r#"          <Data name="{}"><value>{}</value></Data>"#,
+                escape(key),
+                escape(&property_text(value))
+            )
+            .unwrap();
+        }
+        writeln!(out, "        </ExtendedData>").unwrap();
+        write_kml_geometry(&mut out, &result.geometry);
+        writeln!(out, "      </Placemark>").unwrap();
+
+        for waypt in &result.waypoints {
+            writeln!(out, "      <Placemark>").unwrap();
+            writeln!(out, "        <name>{}</name>", escape(&waypt.name)).unwrap();
+            writeln!(
+                out,
+                r#"        <ExtendedData><Data name="snapped"><value>{}</value></Data></ExtendedData>"#,
+                waypt.snapped
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "        <Point><coordinates>{},{}</coordinates></Point>",
+                waypt.pt.x, waypt.pt.y
+            )
+            .unwrap();
+            writeln!(out, "      </Placemark>").unwrap();
+        }
+        writeln!(out, "    </Folder>").unwrap();
+    }
+    writeln!(out, "  </Document>").unwrap();
+    writeln!(out, "</kml>").unwrap();
+    out
+}
+
+fn write_kml_geometry(out: &mut String, geometry: &Geometry) {
+    match geometry {
+        Geometry::LineString(line) => {
+            writeln!(
+                out,
+                "        <LineString><coordinates>{}</coordinates></LineString>",
+                kml_coordinates(line)
+            )
+            .unwrap();
+        }
+        Geometry::Polygon(polygon) => write_kml_polygon(out, polygon),
+        Geometry::MultiPolygon(mp) => {
+            writeln!(out, "        <MultiGeometry>").unwrap();
+            for polygon in mp {
+                write_kml_polygon(out, polygon);
+            }
+            writeln!(out, "        </MultiGeometry>").unwrap();
+        }
+        _ => {}
+    }
+}
+
+fn write_kml_polygon(out: &mut String, polygon: &Polygon) {
+    writeln!(out, "        <Polygon>").unwrap();
+    writeln!(
+        out,
+        "          <outerBoundaryIs><LinearRing><coordinates>{}</coordinates></LinearRing></outerBoundaryIs>",
+        kml_coordinates(polygon.exterior())
+    )
+    .unwrap();
+    for ring in polygon.interiors() {
+        writeln!(
+            out,
+            "          <innerBoundaryIs><LinearRing><coordinates>{}</coordinates></LinearRing></innerBoundaryIs>",
+            kml_coordinates(ring)
+        )
+        .unwrap();
+    }
+    writeln!(out, "        </Polygon>").unwrap();
+}
+
+fn kml_coordinates(line: &LineString) -> String {
+    line.0
+        .iter()
+        .map(|pt| format!("{},{}", pt.x, pt.y))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Writes a GPX file with a route and a track for each result. The route has the named waypoints
+/// and the properties as extensions. The track has a segment for every ring of an area.
+pub fn to_gpx(results: &[RouteResult]) -> String {
+    let routes: Vec<GpxRoute> = results
+        .iter()
+        .map(|result| GpxRoute {
+            name: result.name.clone(),
+            waypoints: result
+                .waypoints
+                .iter()
+                .map(|waypt| GpxPoint {
+                    pt: waypt.pt,
+                    name: Some(waypt.name.clone()),
+                })
+                .collect(),
+            track: lines(&result.geometry),
+            properties: result
+                .properties
+                .iter()
+                .map(|(key, value)| (key.clone(), property_text(value)))
+                .collect(),
+        })
+        .collect();
+    crate::gpx::write_gpx(&routes)
+}
+
+// Every line in a LineString, Polygon, or MultiPolygon
+fn lines(geometry: &Geometry) -> Vec<Vec<Coord>> {
+    let rings = |polygon: &Polygon| {
+        std::iter::once(polygon.exterior())
+            .chain(polygon.interiors())
+            .map(|ring| ring.0.clone())
+            .collect::<Vec<_>>()
+    };
+    match geometry {
+        Geometry::LineString(line) => vec![line.0.clone()],
+        Geometry::Polygon(polygon) => rings(polygon),
+        Geometry::MultiPolygon(mp) => mp.iter().flat_map(rings).collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Strings are written directly, and anything else as JSON
+fn property_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(x) => x.clone(),
+        x => x.to_string(),
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::{HashMap, HashSet};
+
+use geo::{Area, Contains, Coord, LineString, Point, Polygon};
+use route_snapper_graph::{EdgeID, NodeID, RouteSnapperMap};
+use rstar::primitives::{GeomWithData, Line};
+use rstar::RTree;
+
+use crate::{edge_geometry, DirectedEdge, BACKWARDS, FORWARDS};
+
+/// Every directed edge leaving each node, with its angle, ignoring the direction of edges. This
+/// must be rebuilt whenever the graph changes.
+pub struct FaceIndex {
+    outgoing: HashMap<NodeID, Vec<(DirectedEdge, f64)>>,
+}
+
+impl FaceIndex {
+    pub fn new(map: &RouteSnapperMap) -> Self {
+        let mut outgoing: HashMap<NodeID, Vec<(DirectedEdge, f64)>> = HashMap::new();
+        for idx in 0..map.edges.len() {
+            for dir in [FORWARDS, BACKWARDS] {
+                let e = DirectedEdge(EdgeID(idx as u32), dir);
+                outgoing
+                    .entry(src_node(map, e))
+                    .or_default()
+                    .push((e, angle_leaving(map, e)));
+            }
+        }
+        Self { outgoing }
+    }
+}
+
+/// Finds the smallest face of the street network enclosing a point, treating the network as a
+/// planar graph and ignoring the direction of edges. The face is returned as a cycle of directed
+/// edges going counter-clockwise, without any dead-ends sticking into it. Returns `None` if the
+/// point isn't enclosed by anything.
+pub fn enclosing_face(
+    map: &RouteSnapperMap,
+    index: &FaceIndex,
+    edge_segments: &RTree<GeomWithData<Line<[f64; 2]>, EdgeID>>,
+    pt: Coord,
+) -> Option<Vec<DirectedEdge>> {
+    let point = Point::from(pt);
+    let closest = edge_segments.nearest_neighbor(&[pt.x, pt.y])?.data;
+
+    // The point could be on either side of the closest edge, so try both
+    [FORWARDS, BACKWARDS]
+        .into_iter()
+        .filter_map(|dir| {
+            let face = trace_face(map, index, DirectedEdge(closest, dir))?;
+            let polygon = face_polygon(map, &face);
+            (polygon.signed_area() > 0.0 && polygon.contains(&point))
+                .then(|| (face, polygon.unsigned_area()))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(face, _)| face)
+}
+
+/// Combines two counter-clockwise cycles sharing at least one edge into one cycle around both.
+/// Returns `None` if they don't share an edge, or if the result isn't a single cycle.
+pub fn merge_faces(
+    map: &RouteSnapperMap,
+    index: &FaceIndex,
+    face1: &[DirectedEdge],
+    face2: &[DirectedEdge],
+) -> Option<Vec<DirectedEdge>> {
+    // Shared edges are interior to the result. When both faces are counter-clockwise and don't
+    // overlap, they're traversed in opposite directions.
+    let shared: Vec<DirectedEdge> = face1
+        .iter()
+        .filter(|e1| face2.iter().any(|e2| e1.0 == e2.0))
+        .cloned()
+        .collect();
+    if shared.is_empty() || shared.iter().any(|e| !face2.contains(&reverse(*e))) {
+        return None;
+    }
+    let remaining: Vec<DirectedEdge> = face1
+        .iter()
+        .chain(face2.iter())
+        .filter(|e| !shared.iter().any(|s| s.0 == e.0))
+        .cloned()
+        .collect();
+    let first = *remaining.first()?;
+    let remaining_set: HashSet<DirectedEdge> = remaining.iter().cloned().collect();
+
+    let mut merged = vec![first];
+    let mut current = first;
+    loop {
+        // Only follow edges left from the two faces
+        let candidates: Vec<(DirectedEdge, f64)> = index.outgoing[&dst_node(map, current)]
+            .iter()
+            .filter(|(e, _)| remaining_set.contains(e))
+            .cloned()
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        let next = next_clockwise(map, &candidates, current);
+        if next == first {
+            break;
+        }
+        if merged.contains(&next) || !remaining_set.contains(&next) {
+            return None;
+        }
+        merged.push(next);
+        current = next;
+    }
+    // Anything left over means the result isn't one ring
+    if merged.len() != remaining.len() {
+        return None;
+    }
+    Some(merged)
+}
+
+/// The polygon enclosed by a cycle of edges
+pub fn face_polygon(map: &RouteSnapperMap, face: &[DirectedEdge]) -> Polygon {
+    let mut pts = Vec::new();
+    for e in face {
+        pts.extend(edge_geometry(map, *e));
+    }
+    pts.dedup();
+    Polygon::new(LineString::new(pts), Vec::new())
+}
+
+/// The node at the start of the edge
+pub fn src_node(map: &RouteSnapperMap, e: DirectedEdge) -> NodeID {
+    let edge = map.edge(e.0);
+    if e.1 == FORWARDS {
+        edge.node1
+    } else {
+        edge.node2
+    }
+}
+
+fn dst_node(map: &RouteSnapperMap, e: DirectedEdge) -> NodeID {
+    src_node(map, reverse(e))
+}
+
+fn reverse(e: DirectedEdge) -> DirectedEdge {
+    DirectedEdge(e.0, !e.1)
+}
+
+// Keeps the face on the left by always taking the sharpest left turn. Dead-ends are followed to
+// the end and back, then removed.
+fn trace_face(
+    map: &RouteSnapperMap,
+    index: &FaceIndex,
+    start: DirectedEdge,
+) -> Option<Vec<DirectedEdge>> {
+    let mut face = vec![start];
+    let mut current = start;
+    // Each directed edge belongs to exactly one face, so this always finishes. Be defensive anyway.
+    for _ in 0..2 * map.edges.len() {
+        let next = next_clockwise(map, &index.outgoing[&dst_node(map, current)], current);
+        if next == start {
+            return Some(remove_dead_ends(face));
+        }
+        face.push(next);
+        current = next;
+    }
+    None
+}
+
+// After arriving along `incoming`, find the first edge clockwise from the way back. Only turn
+// around if there's no other choice.
+fn next_clockwise(
+    map: &RouteSnapperMap,
+    candidates: &[(DirectedEdge, f64)],
+    incoming: DirectedEdge,
+) -> DirectedEdge {
+    let back = reverse(incoming);
+    let back_angle = angle_leaving(map, back);
+    candidates
+        .iter()
+        .filter(|(e, _)| *e != back)
+        .min_by(|(_, a1), (_, a2)| {
+            let turn1 = (back_angle - a1).rem_euclid(std::f64::consts::TAU);
+            let turn2 = (back_angle - a2).rem_euclid(std::f64::consts::TAU);
+            turn1.total_cmp(&turn2)
+        })
+        .map(|(e, _)| *e)
+        .unwrap_or(back)
+}
+
+// The direction an edge leaves its source node, in radians counter-clockwise from east.
+// Longitude is scaled, so angles are roughly right away from the equator.
+fn angle_leaving(map: &RouteSnapperMap, e: DirectedEdge) -> f64 {
+    let pts = edge_geometry(map, e);
+    let pt1 = pts[0];
+    let pt2 = pts.iter().find(|pt| **pt != pt1).cloned().unwrap_or(pt1);
+    let dx = (pt2.x - pt1.x) * pt1.y.to_radians().cos();
+    (pt2.y - pt1.y).atan2(dx)
+}
+
+// Removes places where the cycle immediately goes back along the same edge
+fn remove_dead_ends(face: Vec<DirectedEdge>) -> Vec<DirectedEdge> {
+    let mut result: Vec<DirectedEdge> = Vec::new();
+    for e in face {
+        if result.last() == Some(&reverse(e)) {
+            result.pop();
+        } else {
+            result.push(e);
+        }
+    }
+    // The dead-end might wrap around the start
+    while result.len() >= 2 && result[0] == reverse(*result.last().unwrap()) {
+        result.pop();
+        result.remove(0);
+    }
+    result
+}
@@ -0,0 +1,354 @@
+//! Writes routes as FlatGeobuf (https://flatgeobuf.org), without a spatial index. FlatGeobuf is
+//! built on FlatBuffers, which is simple enough to write directly for the few tables needed here.
+
+use std::collections::BTreeMap;
+
+use geo::{BoundingRect, Geometry, Polygon};
+use serde_json::Value;
+
+use crate::export::RouteResult;
+
+const MAGIC_BYTES: [u8; 8] = [b'f', b'g', b'b', 3, b'f', b'g', b'b', 0];
+
+// From the FlatGeobuf schema
+const GEOMETRY_TYPE_UNKNOWN: u8 = 0;
+const GEOMETRY_TYPE_LINE_STRING: u8 = 2;
+const GEOMETRY_TYPE_POLYGON: u8 = 3;
+const GEOMETRY_TYPE_MULTI_POLYGON: u8 = 6;
+const COLUMN_TYPE_BOOL: u8 = 2;
+const COLUMN_TYPE_DOUBLE: u8 = 10;
+const COLUMN_TYPE_STRING: u8 = 11;
+const COLUMN_TYPE_JSON: u8 = 12;
+
+/// Writes every route as one feature, with a column for every property. Properties that are
+/// always numbers, booleans, or strings get a column of that type, and anything else is JSON.
+pub fn write(results: &[RouteResult]) -> Vec<u8> {
+    let columns = columns(results);
+    let geometry_type = {
+        let mut types = results.iter().map(|result| geometry_type(&result.geometry));
+        match types.next() {
+            Some(first) if types.all(|x| x == first) => first,
+            _ => GEOMETRY_TYPE_UNKNOWN,
+        }
+    };
+
+    let mut header = vec![
+        (0, Field::Object(Object::String("routes".to_string()))),
+        (2, Field::Scalar(vec![geometry_type])),
+        (
+            7,
+            Field::Object(Object::Tables(
+                columns
+                    .iter()
+                    .map(|(name, column_type)| {
+                        Object::Table(vec![
+                            (0, Field::Object(Object::String(name.to_string()))),
+                            (1, Field::Scalar(vec![*column_type])),
+                        ])
+                    })
+                    .collect(),
+            )),
+        ),
+        (
+            8,
+            Field::Scalar((results.len() as u64).to_le_bytes().to_vec()),
+        ),
+        // No spatial index
+        (9, Field::Scalar(0_u16.to_le_bytes().to_vec())),
+        (
+            10,
+            Field::Object(Object::Table(vec![
+                (0, Field::Object(Object::String("EPSG".to_string()))),
+                (1, Field::Scalar(4326_i32.to_le_bytes().to_vec())),
+            ])),
+        ),
+    ];
+    if let Some(bbox) = results
+        .iter()
+        .filter_map(|result| result.geometry.bounding_rect())
+        .reduce(|a, b| {
+            geo::Rect::new(
+                geo::coord! { x: a.min().x.min(b.min().x), y: a.min().y.min(b.min().y) },
+                geo::coord! { x: a.max().x.max(b.max().x), y: a.max().y.max(b.max().y) },
+            )
+        })
+    {
+        header.push((
+            1,
+            Field::Object(doubles(&[
+                bbox.min().x,
+                bbox.min().y,
+                bbox.max().x,
+                bbox.max().y,
+            ])),
+        ));
+    }
+
+    // Every buffer is a multiple of 8 bytes long, so each one starts aligned in the file
+    let mut out = MAGIC_BYTES.to_vec();
+    out.extend(finish(Object::Table(header)));
+    for result in results {
+        let mut properties = Vec::new();
+        for (idx, (name, column_type)) in columns.iter().enumerate() {
+            let Some(value) = result.properties.get(*name).filter(|x| !x.is_null()) else {
+                continue;
+            };
+            properties.extend_from_slice(&(idx as u16).to_le_bytes());
+            match *column_type {
+                COLUMN_TYPE_DOUBLE => {
+                    properties.extend_from_slice(&value.as_f64().unwrap().to_le_bytes())
+                }
+                COLUMN_TYPE_BOOL => properties.push(value.as_bool().unwrap() as u8),
+                _ => {
+                    let text = match value {
+                        Value::String(x) => x.clone(),
+                        x => x.to_string(),
+                    };
+                    properties.extend_from_slice(&(text.len() as u32).to_le_bytes());
+                    properties.extend_from_slice(text.as_bytes());
+                }
+            }
+        }
+
+        let feature = Object::Table(vec![
+            (0, Field::Object(geometry(&result.geometry))),
+            (
+                1,
+                Field::Object(Object::Vector {
+                    count: properties.len(),
+                    data: properties,
+                    align: 1,
+                }),
+            ),
+        ]);
+        out.extend(finish(feature));
+    }
+    out
+}
+
+// Every property name used by any route, with the column type
+fn columns(results: &[RouteResult]) -> Vec<(&str, u8)> {
+    let mut columns: BTreeMap<&str, u8> = BTreeMap::new();
+    for result in results {
+        for (name, value) in &result.properties {
+            let column_type = match value {
+                Value::Null => continue,
+                Value::Number(_) => COLUMN_TYPE_DOUBLE,
+                Value::Bool(_) => COLUMN_TYPE_BOOL,
+                Value::String(_) => COLUMN_TYPE_STRING,
+                Value::Array(_) | Value::Object(_) => COLUMN_TYPE_JSON,
+            };
+            columns
+                .entry(name)
+                .and_modify(|x| {
+                    if *x != column_type {
+                        *x = COLUMN_TYPE_JSON;
+                    }
+                })
+                .or_insert(column_type);
+        }
+    }
+    columns.into_iter().collect()
+}
+
+fn geometry_type(geometry: &Geometry) -> u8 {
+    match geometry {
+        Geometry::LineString(_) => GEOMETRY_TYPE_LINE_STRING,
+        Geometry::Polygon(_) => GEOMETRY_TYPE_POLYGON,
+        Geometry::MultiPolygon(_) => GEOMETRY_TYPE_MULTI_POLYGON,
+        _ => GEOMETRY_TYPE_UNKNOWN,
+    }
+}
+
+fn geometry(geometry: &Geometry) -> Object {
+    let mut fields = match geometry {
+        Geometry::LineString(line) => {
+            vec![(1, Field::Object(doubles(&flatten(&line.0))))]
+        }
+        Geometry::Polygon(polygon) => polygon_fields(polygon),
+        Geometry::MultiPolygon(mp) => vec![(
+            7,
+            Field::Object(Object::Tables(
+                mp.iter()
+                    .map(|polygon| {
+                        let mut fields = polygon_fields(polygon);
+                        fields.push((6, Field::Scalar(vec![GEOMETRY_TYPE_POLYGON])));
+                        Object::Table(fields)
+                    })
+                    .collect(),
+            )),
+        )],
+        _ => Vec::new(),
+    };
+    fields.push((6, Field::Scalar(vec![geometry_type(geometry)])));
+    Object::Table(fields)
+}
+
+// All of the rings, and the index where each ring ends
+fn polygon_fields(polygon: &Polygon) -> Vec<(u16, Field)> {
+    let mut xy = Vec::new();
+    let mut ends: Vec<u8> = Vec::new();
+    for ring in std::iter::once(polygon.exterior()).chain(polygon.interiors()) {
+        xy.extend(flatten(&ring.0));
+        ends.extend_from_slice(&((xy.len() / 2) as u32).to_le_bytes());
+    }
+    vec![
+        (
+            0,
+            Field::Object(Object::Vector {
+                count: ends.len() / 4,
+                data: ends,
+                align: 4,
+            }),
+        ),
+        (1, Field::Object(doubles(&xy))),
+    ]
+}
+
+fn flatten(pts: &[geo::Coord]) -> Vec<f64> {
+    pts.iter().flat_map(|pt| [pt.x, pt.y]).collect()
+}
+
+fn doubles(values: &[f64]) -> Object {
+    Object::Vector {
+        count: values.len(),
+        data: values.iter().flat_map(|x| x.to_le_bytes()).collect(),
+        align: 8,
+    }
+}
+
+// Something in a FlatBuffer
+enum Object {
+    // Fields by their ID in the schema. Missing fields use the default.
+    Table(Vec<(u16, Field)>),
+    String(String),
+    // Little-endian scalars
+    Vector {
+        count: usize,
+        data: Vec<u8>,
+        align: usize,
+    },
+    Tables(Vec<Object>),
+}
+
+enum Field {
+    // Little-endian bytes, stored inline and aligned to their size
+    Scalar(Vec<u8>),
+    // Stored after the table, and referred to by an offset
+    Object(Object),
+}
+
+// Writes a complete FlatBuffer, prefixed by its size. The usual builders write back to front, but
+// this writes front to back, placing everything after whatever refers to it, so every offset is
+// positive. Readers align relative to the start of the size prefix, so this does too, and pads
+// the end to a multiple of 8 bytes.
+fn finish(root: Object) -> Vec<u8> {
+    let mut buf = vec![0; 8];
+    let pos = write_object(&mut buf, &root);
+    pad(&mut buf, 8, 0);
+    let size = buf.len() - 4;
+    buf[0..4].copy_from_slice(&(size as u32).to_le_bytes());
+    // The root offset is relative to where it's stored, just after the size
+    patch_offset(&mut buf, 4, pos);
+    buf
+}
+
+// Appends the object and returns where it starts
+fn write_object(buf: &mut Vec<u8>, object: &Object) -> usize {
+    match object {
+        Object::String(x) => {
+            pad(buf, 4, 0);
+            let pos = buf.len();
+            buf.extend_from_slice(&(x.len() as u32).to_le_bytes());
+            buf.extend_from_slice(x.as_bytes());
+            buf.push(0);
+            pos
+        }
+        Object::Vector { count, data, align } => {
+            // The length comes first, and the elements after must be aligned
+            pad(buf, (*align).max(4), 4);
+            let pos = buf.len();
+            buf.extend_from_slice(&(*count as u32).to_le_bytes());
+            buf.extend_from_slice(data);
+            pos
+        }
+        Object::Tables(tables) => {
+            pad(buf, 4, 0);
+            let pos = buf.len();
+            buf.extend_from_slice(&(tables.len() as u32).to_le_bytes());
+            let slots: Vec<usize> = tables
+                .iter()
+                .map(|_| {
+                    buf.extend_from_slice(&[0; 4]);
+                    buf.len() - 4
+                })
+                .collect();
+            for (table, slot) in tables.iter().zip(slots) {
+                let table_pos = write_object(buf, table);
+                patch_offset(buf, slot, table_pos);
+            }
+            pos
+        }
+        Object::Table(fields) => {
+            // Lay out the table after its offset to the vtable, aligning each field
+            let mut layout = Vec::new();
+            let mut size: usize = 4;
+            let mut align = 4;
+            for (id, field) in fields {
+                let len = match field {
+                    Field::Scalar(bytes) => bytes.len(),
+                    Field::Object(_) => 4,
+                };
+                size = size.div_ceil(len) * len;
+                layout.push((*id, size));
+                size += len;
+                align = align.max(len);
+            }
+
+            // The vtable comes first, with the offset of every field, or 0 if it's missing
+            let num_slots = fields.iter().map(|(id, _)| *id + 1).max().unwrap_or(0);
+            pad(buf, 2, 0);
+            let vtable_pos = buf.len();
+            buf.extend_from_slice(&(4 + 2 * num_slots).to_le_bytes());
+            buf.extend_from_slice(&(size as u16).to_le_bytes());
+            for slot in 0..num_slots {
+                let offset = layout
+                    .iter()
+                    .find(|(id, _)| *id == slot)
+                    .map(|(_, offset)| *offset as u16)
+                    .unwrap_or(0);
+                buf.extend_from_slice(&offset.to_le_bytes());
+            }
+
+            pad(buf, align, 0);
+            let table_pos = buf.len();
+            buf.extend_from_slice(&((table_pos - vtable_pos) as i32).to_le_bytes());
+            buf.resize(table_pos + size, 0);
+            for ((_, field), (_, offset)) in fields.iter().zip(&layout) {
+                if let Field::Scalar(bytes) = field {
+                    buf[table_pos + offset..table_pos + offset + bytes.len()]
+                        .copy_from_slice(bytes);
+                }
+            }
+            for ((_, field), (_, offset)) in fields.iter().zip(&layout) {
+                if let Field::Object(object) = field {
+                    let pos = write_object(buf, object);
+                    patch_offset(buf, table_pos + offset, pos);
+                }
+            }
+            table_pos
+        }
+    }
+}
+
+// Offsets are relative to where they're stored
+fn patch_offset(buf: &mut [u8], slot: usize, target: usize) {
+    buf[slot..slot + 4].copy_from_slice(&((target - slot) as u32).to_le_bytes());
+}
+
+// Adds zeroes until `extra` more bytes would end aligned
+fn pad(buf: &mut Vec<u8>, align: usize, extra: usize) {
+    while !(buf.len() + extra).is_multiple_of(align) {
+        buf.push(0);
+    }
+}
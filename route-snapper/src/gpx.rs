@@ -0,0 +1,205 @@
+//! Reads and writes routes as GPX 1.1, without depending on the rest of the snapper.
+
+use std::fmt::Write;
+
+use geo::Coord;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+/// A point in a GPX file, with an optional name
+#[derive(Clone, Debug, PartialEq)]
+pub struct GpxPoint {
+    pub pt: Coord,
+    pub name: Option<String>,
+}
+
+/// A route to write in a GPX file
+pub struct GpxRoute {
+    pub name: String,
+    /// Written as named route points
+    pub waypoints: Vec<GpxPoint>,
+    /// The full geometry, written as one track with a segment for each line
+    pub track: Vec<Vec<Coord>>,
+    /// Extra key/value pairs describing the route, written as extensions
+    pub properties: Vec<(String, String)>,
+}
+
+// The namespace for extensions
+const EXTENSIONS_NAMESPACE: &str = "https://github.com/dabreegster/route_snapper";
+
+/// Writes a GPX file with each route's waypoints as route points, and its full geometry as a
+/// track. Any properties are written as `<rs:property name="key">value</rs:property>` extensions
+/// of the route.
+pub fn write_gpx(routes: &[GpxRoute]) -> String {
+    let mut out = String::new();
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(
+        out,
+        r#"<gpx version="1.1" creator="route-snapper" xmlns="http://www.topografix.com/GPX/1/1" xmlns:rs="{EXTENSIONS_NAMESPACE}">"#
+    )
+    .unwrap();
+    if let [route] = routes {
+        writeln!(
+            out,
+            "  <metadata><name>{}</name></metadata>",
+            escape(&route.name)
+        )
+        .unwrap();
+    }
+
+    for route in routes {
+        writeln!(out, "  <rte>").unwrap();
+        writeln!(out, "    <name>{}</name>", escape(&route.name)).unwrap();
+        if !route.properties.is_empty() {
+            writeln!(out, "    <extensions>").unwrap();
+            for (key, value) in &route.properties {
+                writeln!(
+                    out,
+                    r#"      <rs:property name="{}">{}</rs:property>"#,
+                    escape(key),
+                    escape(value)
+                )
+                .unwrap();
+            }
+            writeln!(out, "    </extensions>").unwrap();
+        }
+        for waypt in &route.waypoints {
+            write!(
+                out,
+                r#"    <rtept lat="{}" lon="{}">"#,
+                waypt.pt.y, waypt.pt.x
+            )
+            .unwrap();
+            if let Some(ref name) = waypt.name {
+                write!(out, "<name>{}</name>", escape(name)).unwrap();
+            }
+            writeln!(out, "</rtept>").unwrap();
+        }
+        writeln!(out, "  </rte>").unwrap();
+    }
+
+    for route in routes {
+        writeln!(out, "  <trk>").unwrap();
+        writeln!(out, "    <name>{}</name>", escape(&route.name)).unwrap();
+        for segment in &route.track {
+            writeln!(out, "    <trkseg>").unwrap();
+            for pt in segment {
+                writeln!(out, r#"      <trkpt lat="{}" lon="{}"/>"#, pt.y, pt.x).unwrap();
+            }
+            writeln!(out, "    </trkseg>").unwrap();
+        }
+        writeln!(out, "  </trk>").unwrap();
+    }
+    writeln!(out, "</gpx>").unwrap();
+    out
+}
+
+/// Reads the route points from a GPX file. If there are none, uses the waypoints instead. Only
+/// the first route is used.
+pub fn read_route_points(input: &str) -> Result<Vec<GpxPoint>, String> {
+    let mut points = read_points(input, "rtept", true)?;
+    if points.is_empty() {
+        points = read_points(input, "wpt", false)?;
+    }
+    if points.is_empty() {
+        return Err("The GPX file has no route points or waypoints".to_string());
+    }
+    Ok(points)
+}
+
+/// Reads every track point in a GPX file, in order across all tracks and segments. If there are
+/// none, uses the route points or waypoints instead.
+pub fn read_track_points(input: &str) -> Result<Vec<GpxPoint>, String> {
+    let points = read_points(input, "trkpt", false)?;
+    if points.is_empty() {
+        return read_route_points(input);
+    }
+    Ok(points)
+}
+
+// Reads every `tag` element as a point, in document order, ignoring namespace prefixes. If
+// `first_route_only`, stops collecting after the first `rte` element ends.
+fn read_points(input: &str, tag: &str, first_route_only: bool) -> Result<Vec<GpxPoint>, String> {
+    let mut reader = Reader::from_str(input);
+    reader.config_mut().expand_empty_elements = true;
+
+    let mut points = Vec::new();
+    // How many elements are open
+    let mut depth = 0;
+    // The point being read, the depth of its element, and its name so far
+    let mut current: Option<(Coord, usize, String)> = None;
+    // Whether the text being read is the current point's name
+    let mut in_name = false;
+    let mut done = false;
+    loop {
+        match reader.read_event().map_err(|err| err.to_string())? {
+            Event::Start(e) => {
+                depth += 1;
+                let name = e.local_name();
+                if let Some((_, point_depth, _)) = current {
+                    in_name = depth == point_depth + 1 && name.as_ref() == b"name";
+                } else if !done && name.as_ref() == tag.as_bytes() {
+                    current = Some((read_coord(&e)?, depth, String::new()));
+                }
+            }
+            Event::End(e) => {
+                in_name = false;
+                if let Some((pt, _, name)) = current.take_if(|x| x.1 == depth) {
+                    let name = name.trim();
+                    points.push(GpxPoint {
+                        pt,
+                        name: (!name.is_empty()).then(|| name.to_string()),
+                    });
+                }
+                if first_route_only && e.local_name().as_ref() == b"rte" {
+                    done = true;
+                }
+                depth -= 1;
+            }
+            Event::Text(e) if in_name => {
+                let text = e.unescape().map_err(|err| err.to_string())?;
+                current.as_mut().unwrap().2.push_str(&text);
+            }
+            Event::CData(e) if in_name => {
+                let text = e.decode().map_err(|err| err.to_string())?;
+                current.as_mut().unwrap().2.push_str(&text);
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err("The GPX file ends before all elements are closed".to_string());
+    }
+    Ok(points)
+}
+
+fn read_coord(element: &BytesStart) -> Result<Coord, String> {
+    Ok(Coord {
+        x: number_attribute(element, "lon", 180.0)?,
+        y: number_attribute(element, "lat", 90.0)?,
+    })
+}
+
+// Parses a number no further than `max` from 0. This rejects NaN and infinity.
+fn number_attribute(element: &BytesStart, key: &str, max: f64) -> Result<f64, String> {
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|err| err.to_string())?;
+        if attribute.key.local_name().as_ref() == key.as_bytes() {
+            let value = attribute.unescape_value().map_err(|err| err.to_string())?;
+            return match value.trim().parse::<f64>() {
+                Ok(x) if x.abs() <= max => Ok(x),
+                _ => Err(format!("Bad {key} {value}")),
+            };
+        }
+    }
+    Err(format!("A point is missing {key}"))
+}
+
+pub(crate) fn escape(x: &str) -> String {
+    x.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -0,0 +1,98 @@
+use std::collections::VecDeque;
+
+use crate::Waypoint;
+
+const MAX_PREVIOUS_STATES: usize = 100;
+
+// Copies of the waypoints of every ring in a route are sufficient to represent state. The first
+// is the ring being edited.
+type State = Vec<Vec<Waypoint>>;
+
+/// Undo and redo stacks for a route's waypoints. Once the undo stack is full, the oldest state
+/// is dropped.
+#[derive(Default)]
+pub struct History {
+    undo: VecDeque<State>,
+    redo: Vec<State>,
+    // While a group of changes is in progress, the state before the first change
+    group_start: Option<State>,
+}
+
+impl History {
+    /// Restores history previously returned by `undo_stack` and `redo_stack`.
+    pub fn from_stacks(undo: Vec<State>, redo: Vec<State>) -> Self {
+        let mut history = Self::default();
+        for state in undo {
+            history.push_undo(state);
+        }
+        history.redo = redo;
+        history
+    }
+
+    /// Ordered from oldest to newest
+    pub fn undo_stack(&self) -> impl Iterator<Item = &State> {
+        self.undo.iter()
+    }
+
+    /// The last state is the next one to redo
+    pub fn redo_stack(&self) -> &[State] {
+        &self.redo
+    }
+
+    /// Call before changing the route. Starting a new change discards anything that could be
+    /// redone. Inside a group, this does nothing.
+    pub fn record(&mut self, current: &State) {
+        if self.group_start.is_some() {
+            return;
+        }
+        self.push_undo(current.clone());
+        self.redo.clear();
+    }
+
+    /// Any changes until `end_group` will be undone as a single step. If a group is already in
+    /// progress, this does nothing.
+    pub fn start_group(&mut self, current: &State) {
+        if self.group_start.is_none() {
+            self.group_start = Some(current.clone());
+        }
+    }
+
+    /// Finishes a group. If nothing changed, no step is recorded.
+    pub fn end_group(&mut self, current: &State) {
+        if let Some(start) = self.group_start.take() {
+            if start != *current {
+                self.push_undo(start);
+                self.redo.clear();
+            }
+        }
+    }
+
+    /// Returns the state to restore, if there is one.
+    pub fn undo(&mut self, current: &State) -> Option<State> {
+        let state = self.undo.pop_back()?;
+        self.redo.push(current.clone());
+        Some(state)
+    }
+
+    /// Returns the state to restore, if there is one.
+    pub fn redo(&mut self, current: &State) -> Option<State> {
+        let state = self.redo.pop()?;
+        self.push_undo(current.clone());
+        Some(state)
+    }
+
+    pub fn undo_length(&self) -> usize {
+        self.undo.len()
+    }
+
+    pub fn redo_length(&self) -> usize {
+        self.redo.len()
+    }
+
+    fn push_undo(&mut self, state: State) {
+        if self.undo.len() == MAX_PREVIOUS_STATES {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(state);
+    }
+}
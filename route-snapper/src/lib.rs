@@ -1,41 +1,105 @@
 #[macro_use]
 extern crate log;
 
+mod directions;
+pub mod export;
+mod faces;
+mod flatgeobuf;
+pub mod gpx;
+mod history;
+mod matching;
+mod naming;
 #[cfg(test)]
 mod tests;
+mod validity;
 
-use std::collections::{BTreeMap, BTreeSet, HashSet};
-use std::fmt::Write;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::sync::Once;
 
 use geo::{
-    line_measures::LengthMeasurable, Coord, Distance, Haversine, InterpolatableLine, Line,
-    LineString, Point, Polygon,
+    line_measures::LengthMeasurable, Area, BoundingRect, Closest, ClosestPoint, Contains, Coord,
+    Distance, GeodesicArea, Haversine, InterpolatableLine, Intersects, Line, LineString,
+    MultiPolygon, Point, Polygon,
 };
 use geojson::{Feature, FeatureCollection, Geometry};
 use petgraph::graphmap::DiGraphMap;
 use rstar::primitives::GeomWithData;
-use rstar::RTree;
+use rstar::{RTree, AABB};
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
-use route_snapper_graph::{EdgeID, NodeID, RouteSnapperMap};
+use self::faces::FaceIndex;
+use self::history::History;
+use self::naming::Naming;
+use route_snapper_graph::{
+    ComponentID, ContractionHierarchy, EdgeID, EdgeSegmentRTree, GraphChanges, GraphPatch, NewEdge,
+    NodeID, NodeRTree, RouteSnapperMap,
+};
 
 static START: Once = Once::new();
 
-const MAX_PREVIOUS_STATES: usize = 100;
+// Increase this whenever the format of Session changes
+const SESSION_VERSION: usize = 1;
+
+// When looking for alternative routes, each time an edge is used by a candidate, multiply its cost
+// by this
+const ALTERNATIVE_PENALTY: f64 = 1.5;
+// Alternatives may cost at most this much more than the current route
+const ALTERNATIVE_MAX_DETOUR: f64 = 1.5;
+// Alternatives may share at most this fraction of their length with any other route
+const ALTERNATIVE_MAX_OVERLAP: f64 = 0.7;
+// Give up trying to make the route follow an alternative after adding shaping waypoints this many
+// times
+const MAX_SHAPING_ATTEMPTS: usize = 5;
+// Roughly the length of a degree of latitude
+const METERS_PER_DEGREE: f64 = 111_320.0;
+// Name at most this many streets in a route's "via" description
+const MAX_VIA_STREETS: usize = 2;
+// Only mention streets covering at least this fraction of the route's named length
+const VIA_MIN_FRACTION: f64 = 0.1;
 
 type Graph = DiGraphMap<NodeID, DirectedEdge>;
 
 #[wasm_bindgen]
 pub struct JsRouteSnapper {
     router: Router,
-    snap_to_nodes: RTree<GeomWithData<[f64; 2], NodeID>>,
+    snap_to_nodes: NodeRTree,
+    // Every segment of every edge, for finding the closest edge to a point
+    edge_segments: EdgeSegmentRTree,
+    // The route currently being edited
     route: Route,
     mode: Mode,
     snap_mode: bool,
-    // Copies of route.waypoints are sufficient to represent state
-    previous_states: Vec<Vec<Waypoint>>,
+    history: History,
+    active_route: u32,
+    // Every other route, keyed by ID
+    inactive_routes: BTreeMap<u32, StoredRoute>,
+    next_route_id: u32,
+    // Alternative routes being previewed for one leg of the route
+    alternatives: Option<Alternatives>,
+    // Every edit made to the graph since loading it
+    graph_patch: GraphPatch,
+    // Fix invalid area polygons before returning them
+    repair_areas: bool,
+    // Don't add or move snapped waypoints if no path can reach them
+    refuse_disconnected: bool,
+    // Only snap new waypoints to nodes in the same component as the previous one
+    snap_within_component: bool,
+    naming: Naming,
+    // Built the first time areaFromPoint is used, and cleared when the graph changes
+    face_index: Option<FaceIndex>,
+}
+
+// A route that isn't currently being edited, along with its own undo history
+struct StoredRoute {
+    route: Route,
+    history: History,
+}
+
+struct Alternatives {
+    // The index of the first waypoint of the leg
+    leg: usize,
+    paths: Vec<Vec<PathEntry>>,
 }
 
 #[derive(Default, Serialize, Deserialize)]
@@ -58,6 +122,18 @@ struct Router {
     map: RouteSnapperMap,
     graph: Graph,
     config: Config,
+    // Optionally loaded to speed up pathfinding on large graphs
+    ch: Option<ContractionHierarchy>,
+    // Zones set by the user, with the multiplier for each
+    zones: Vec<(MultiPolygon, f64)>,
+    // From zones, multiply the cost of these edges. Infinity blocks the edge.
+    zone_multipliers: HashMap<EdgeID, f64>,
+    // Replaces the cost of crossing an edge in one direction. None closes the edge.
+    cost_overrides: HashMap<(EdgeID, Direction), Option<f64>>,
+    // Per node, the strongly connected component using current costs
+    components: Vec<ComponentID>,
+    // Per node, the component ignoring costs and direction entirely
+    weak_components: Vec<ComponentID>,
 }
 
 // TODO It's impossible for a waypoint to be an Edge, but the code might be simpler if this and
@@ -101,6 +177,13 @@ impl PathEntry {
             PathEntry::Edge(_) => None,
         }
     }
+
+    fn to_snapped_node(self) -> Option<NodeID> {
+        match self {
+            PathEntry::SnappedPoint(x) => Some(x),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -111,13 +194,18 @@ struct Route {
     // The full route, expanded. This can be calculated purely from waypoints.
     // TODO Sometimes this has duplicate adjacent entries, for unknown reasons
     full_path: Vec<PathEntry>,
+
+    // In area mode, other closed rings. A ring inside another becomes a hole; otherwise it's
+    // another part of a MultiPolygon. To edit one, swap it with waypoints and full_path. These
+    // never have their own other_rings.
+    other_rings: Vec<Route>,
 }
 
 type Direction = bool;
 const FORWARDS: Direction = true;
 const BACKWARDS: Direction = false;
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 struct DirectedEdge(EdgeID, Direction);
 
 #[derive(Clone, Debug, PartialEq)]
@@ -126,6 +214,9 @@ enum Mode {
     // TODO It'd be simpler if this was only hovering on an existing node. Make a new state for
     // appending a snapped point.
     Hovering(Waypoint),
+    // Hovering somewhere on the route's line, away from any point. full_idx is the entry in
+    // full_path where this piece of line starts, and pt is the closest point on the line.
+    HoveringLine { full_idx: usize, pt: Coord },
     // idx is into full_path
     Dragging { idx: usize, at: Waypoint },
     // TODO Rename? This is appending a freehand
@@ -147,46 +238,11 @@ impl JsRouteSnapper {
         info!("Got {} bytes, deserializing", map_bytes.len());
 
         let mut map: RouteSnapperMap = bincode::deserialize(map_bytes).map_err(err_to_js)?;
-
-        if !map.override_forward_costs.is_empty()
-            && map.override_forward_costs.len() != map.edges.len()
-        {
-            return Err(JsValue::from_str(
-                "override_forward_costs length doesn't match edges length",
-            ));
-        }
-        if !map.override_backward_costs.is_empty()
-            && map.override_backward_costs.len() != map.edges.len()
-        {
-            return Err(JsValue::from_str(
-                "override_backward_costs length doesn't match edges length",
-            ));
-        }
-
-        for (idx, edge) in map.edges.iter_mut().enumerate() {
-            edge.length_meters = edge.geometry.length(&Haversine);
-
-            if map.override_forward_costs.is_empty() {
-                edge.forward_cost = Some(edge.length_meters);
-            } else {
-                edge.forward_cost = map.override_forward_costs[idx];
-            }
-
-            if map.override_backward_costs.is_empty() {
-                edge.backward_cost = Some(edge.length_meters);
-            } else {
-                edge.backward_cost = map.override_backward_costs[idx];
-            }
-        }
+        map.finalize().map_err(err_to_js)?;
 
         info!("Finalizing JsRouteSnapper");
 
-        let mut graph: Graph = DiGraphMap::new();
-        for (idx, e) in map.edges.iter().enumerate() {
-            let id = EdgeID(idx as u32);
-            graph.add_edge(e.node1, e.node2, DirectedEdge(id, FORWARDS));
-            graph.add_edge(e.node2, e.node1, DirectedEdge(id, BACKWARDS));
-        }
+        let graph: Graph = DiGraphMap::new();
 
         // Euclidean distance on WGS84 coordinates works because we're just finding the closest
         // point to the cursor, and always in a pretty small area. Using GeodesicDistance as a
@@ -196,21 +252,211 @@ impl JsRouteSnapper {
             nodes.push(GeomWithData::new([pt.x, pt.y], NodeID(idx as u32)));
         }
         let snap_to_nodes = RTree::bulk_load(nodes);
+        let edge_segments = map.index_edge_segments();
+
+        let mut router = Router {
+            map,
+            graph,
+            config: Config::default(),
+            ch: None,
+            zones: Vec::new(),
+            zone_multipliers: HashMap::new(),
+            cost_overrides: HashMap::new(),
+            components: Vec::new(),
+            weak_components: Vec::new(),
+        };
+        for idx in 0..router.map.edges.len() {
+            router.add_to_graph(EdgeID(idx as u32));
+        }
+        router.update_components();
 
         Ok(Self {
-            router: Router {
-                map,
-                graph,
-                config: Config::default(),
-            },
+            router,
             snap_to_nodes,
+            edge_segments,
             route: Route::new(),
             mode: Mode::Neutral,
             snap_mode: true,
-            previous_states: Vec::new(),
+            history: History::default(),
+            active_route: 0,
+            inactive_routes: BTreeMap::new(),
+            next_route_id: 1,
+            alternatives: None,
+            graph_patch: GraphPatch::default(),
+            repair_areas: false,
+            refuse_disconnected: false,
+            snap_within_component: false,
+            naming: Naming::default(),
+            face_index: None,
         })
     }
 
+    /// Loads a contraction hierarchy built from the same graph by the `contract-graph` tool. When
+    /// present, it's used to speed up pathfinding.
+    #[wasm_bindgen(js_name = loadContractionHierarchy)]
+    pub fn load_contraction_hierarchy(&mut self, ch_bytes: &[u8]) -> Result<(), JsValue> {
+        info!("Got {} bytes, deserializing", ch_bytes.len());
+        let mut ch: ContractionHierarchy = bincode::deserialize(ch_bytes).map_err(err_to_js)?;
+        ch.finalize(&self.router.map).map_err(err_to_js)?;
+        self.router.ch = Some(ch);
+        Ok(())
+    }
+
+    /// Sets zones that change routing, replacing any previous zones, and recalculates paths. The
+    /// input is a GeoJSON FeatureCollection of Polygons and MultiPolygons. Each feature must have
+    /// either a numeric `multiplier` property, applied to the cost of every edge intersecting the
+    /// zone, or `"block": true` to avoid those edges entirely. The caller should redraw.
+    #[wasm_bindgen(js_name = setZones)]
+    pub fn set_zones(&mut self, input: String) -> Result<(), JsValue> {
+        let fc: FeatureCollection = input.parse().map_err(err_to_js)?;
+        let mut zones = Vec::new();
+        for f in fc.features {
+            let multiplier = if f.property("block").and_then(|x| x.as_bool()) == Some(true) {
+                f64::INFINITY
+            } else if let Some(x) = f.property("multiplier").and_then(|x| x.as_f64()) {
+                if !x.is_finite() || x <= 0.0 {
+                    return Err(JsValue::from_str("Zone multipliers must be positive"));
+                }
+                x
+            } else {
+                return Err(JsValue::from_str(
+                    "Every zone needs a multiplier or block property",
+                ));
+            };
+            let Some(geometry) = f.geometry else {
+                return Err(JsValue::from_str("A zone is missing geometry"));
+            };
+            let polygons = match geo::Geometry::try_from(geometry).map_err(err_to_js)? {
+                geo::Geometry::Polygon(p) => MultiPolygon(vec![p]),
+                geo::Geometry::MultiPolygon(mp) => mp,
+                _ => return Err(JsValue::from_str("Zones must be Polygons or MultiPolygons")),
+            };
+            zones.push((polygons, multiplier));
+        }
+
+        self.router.zones = zones;
+        self.router.zone_multipliers.clear();
+        for idx in 0..self.router.map.edges.len() {
+            self.router.apply_zones(EdgeID(idx as u32));
+        }
+
+        self.after_cost_change();
+        Ok(())
+    }
+
+    /// Overrides the cost of crossing an edge in one direction and recalculates paths. If `cost`
+    /// is missing, the edge is closed in that direction. The caller should redraw.
+    #[wasm_bindgen(js_name = setEdgeCost)]
+    pub fn set_edge_cost(
+        &mut self,
+        edge_id: u32,
+        forwards: bool,
+        cost: Option<f64>,
+    ) -> Result<(), JsValue> {
+        self.check_edge_id(edge_id)?;
+        if let Some(cost) = cost {
+            if !cost.is_finite() || cost < 0.0 {
+                return Err(JsValue::from_str("Edge costs must be non-negative"));
+            }
+        }
+        let dir = if forwards { FORWARDS } else { BACKWARDS };
+        self.router
+            .cost_overrides
+            .insert((EdgeID(edge_id), dir), cost);
+        self.after_cost_change();
+        Ok(())
+    }
+
+    /// Closes an edge in both directions and recalculates paths. The caller should redraw.
+    #[wasm_bindgen(js_name = closeEdge)]
+    pub fn close_edge(&mut self, edge_id: u32) -> Result<(), JsValue> {
+        self.check_edge_id(edge_id)?;
+        for dir in [FORWARDS, BACKWARDS] {
+            self.router
+                .cost_overrides
+                .insert((EdgeID(edge_id), dir), None);
+        }
+        self.after_cost_change();
+        Ok(())
+    }
+
+    /// Removes any cost overrides or closures for an edge in both directions and recalculates
+    /// paths. The caller should redraw.
+    #[wasm_bindgen(js_name = restoreEdge)]
+    pub fn restore_edge(&mut self, edge_id: u32) -> Result<(), JsValue> {
+        self.check_edge_id(edge_id)?;
+        for dir in [FORWARDS, BACKWARDS] {
+            self.router.cost_overrides.remove(&(EdgeID(edge_id), dir));
+        }
+        self.after_cost_change();
+        Ok(())
+    }
+
+    /// Adds a new edge to the graph and recalculates paths. The input is a GeoJSON Feature with a
+    /// LineString, and optional `name`, `forward_cost`, and `backward_cost` properties. Costs must
+    /// be non-negative, and a missing cost means the edge isn't routable in that direction. Each
+    /// end of the LineString connects to an existing node within `snap_distance_meters`.
+    /// Otherwise, it splits an existing edge within that distance, or else creates a new node.
+    /// Returns the new edge ID. The caller should redraw.
+    #[wasm_bindgen(js_name = addEdge)]
+    pub fn add_edge(&mut self, input: String, snap_distance_meters: f64) -> Result<u32, JsValue> {
+        let f: Feature = input.parse().map_err(err_to_js)?;
+        let Some(geojson::Value::LineString(ref coords)) = f.geometry.as_ref().map(|g| &g.value)
+        else {
+            return Err(JsValue::from_str("A new edge must be a LineString"));
+        };
+        let new_edge = NewEdge {
+            geometry: coords.iter().map(|pt| [pt[0], pt[1]]).collect(),
+            name: f
+                .property("name")
+                .and_then(|x| x.as_str())
+                .map(|x| x.to_string()),
+            forward_cost: f.property("forward_cost").and_then(|x| x.as_f64()),
+            backward_cost: f.property("backward_cost").and_then(|x| x.as_f64()),
+        };
+
+        let changes = self
+            .router
+            .map
+            .add_edge(
+                &new_edge,
+                snap_distance_meters,
+                &mut self.snap_to_nodes,
+                &mut self.edge_segments,
+            )
+            .map_err(err_to_js)?;
+        let id = changes.new_edge;
+        self.graph_patch.new_edges.push(changes.resolved.clone());
+        self.after_graph_change(vec![changes]);
+        Ok(id.0)
+    }
+
+    /// Returns every edge added since loading the graph, as JSON. This can be passed to
+    /// `applyGraphPatch` after loading the same graph again.
+    #[wasm_bindgen(js_name = exportGraphPatch)]
+    pub fn export_graph_patch(&self) -> String {
+        serde_json::to_string_pretty(&self.graph_patch).unwrap()
+    }
+
+    /// Applies edits previously returned by `exportGraphPatch` and recalculates paths. The caller
+    /// should redraw.
+    #[wasm_bindgen(js_name = applyGraphPatch)]
+    pub fn apply_graph_patch(&mut self, input: String) -> Result<(), JsValue> {
+        let patch: GraphPatch = serde_json::from_str(&input).map_err(err_to_js)?;
+        let changes = patch
+            .apply(
+                &mut self.router.map,
+                &mut self.snap_to_nodes,
+                &mut self.edge_segments,
+            )
+            .map_err(err_to_js)?;
+        self.graph_patch
+            .new_edges
+            .extend(changes.iter().map(|x| x.resolved.clone()));
+        self.after_graph_change(changes);
+        Ok(())
+    }
+
     /// Updates configuration and recalculates paths. The caller should redraw.
     #[wasm_bindgen(js_name = setRouteConfig)]
     pub fn set_route_config(&mut self, input: JsValue) {
@@ -218,7 +464,7 @@ impl JsRouteSnapper {
             Ok(config) => {
                 self.router.config = config;
                 assert!(!self.router.config.area_mode);
-                self.route.recalculate_full_path(&self.router);
+                self.recalculate_all_routes();
             }
             Err(err) => {
                 error!("Bad input to setRouteConfig: {err}");
@@ -235,7 +481,41 @@ impl JsRouteSnapper {
             extend_route: true,
             area_mode: true,
         };
-        self.route.recalculate_full_path(&self.router);
+        self.recalculate_all_routes();
+    }
+
+    /// In area mode, decides whether to fix invalid polygons, by removing spikes where the boundary
+    /// doubles back, resolving self-intersections, and dropping parts with no area. This affects
+    /// `toFinalFeature` and the polygon drawn by `renderGeojson`. Problems with the waypoints
+    /// themselves are still reported.
+    #[wasm_bindgen(js_name = setRepairAreas)]
+    pub fn set_repair_areas(&mut self, enabled: bool) {
+        self.repair_areas = enabled;
+    }
+
+    /// Decides whether to refuse adding or dragging snapped waypoints when no path connects them
+    /// to their neighbors. When disabled, those legs are drawn as straight lines and flagged as
+    /// disconnected.
+    #[wasm_bindgen(js_name = setRefuseDisconnected)]
+    pub fn set_refuse_disconnected(&mut self, enabled: bool) {
+        self.refuse_disconnected = enabled;
+    }
+
+    /// Decides whether to only snap to nodes in the same strongly connected component as the last
+    /// snapped waypoint, meaning the route can reach them and get back. This skips small islands
+    /// in the graph, like car parks only reachable from private roads.
+    #[wasm_bindgen(js_name = setSnapWithinComponent)]
+    pub fn set_snap_within_component(&mut self, enabled: bool) {
+        self.snap_within_component = enabled;
+    }
+
+    /// Changes the text used to name routes, areas, and waypoints, and optionally the language of
+    /// street names. The input is JSON with any of the fields of `Naming`; missing fields use the
+    /// English defaults.
+    #[wasm_bindgen(js_name = setNaming)]
+    pub fn set_naming(&mut self, input: String) -> Result<(), JsValue> {
+        self.naming = serde_json::from_str(&input).map_err(err_to_js)?;
+        Ok(())
     }
 
     /// Gets the current configuration in JSON.
@@ -244,70 +524,169 @@ impl JsRouteSnapper {
         serde_json::to_string_pretty(&self.router.config).unwrap()
     }
 
-    #[wasm_bindgen(js_name = toFinalFeature)]
-    pub fn to_final_feature(&self) -> Option<String> {
-        let mut feature = if self.router.config.area_mode {
-            if let Some(polygon) = self.into_polygon_area() {
-                Feature::from(polygon)
-            } else {
-                return None;
-            }
+    /// Starts a new empty route and makes it active. The previously active route is kept. Returns
+    /// the new route's ID.
+    #[wasm_bindgen(js_name = newRoute)]
+    pub fn new_route(&mut self) -> u32 {
+        let id = self.next_route_id;
+        self.next_route_id += 1;
+        self.stash_active_route();
+        self.reset_active_route();
+        self.active_route = id;
+        id
+    }
+
+    /// Changes which route is being edited. Each route keeps its own undo history. The caller
+    /// should redraw.
+    #[wasm_bindgen(js_name = setActiveRoute)]
+    pub fn set_active_route(&mut self, id: u32) -> Result<(), JsValue> {
+        if id == self.active_route {
+            return Ok(());
+        }
+        if !self.inactive_routes.contains_key(&id) {
+            return Err(JsValue::from_str(&format!("No route with ID {id}")));
+        }
+        self.activate_route(id);
+        Ok(())
+    }
+
+    /// Deletes a route. If it's the active route, the remaining route with the lowest ID becomes
+    /// active. If it's the only route, it's just cleared. The caller should redraw.
+    #[wasm_bindgen(js_name = deleteRoute)]
+    pub fn delete_route(&mut self, id: u32) -> Result<(), JsValue> {
+        if id != self.active_route {
+            return match self.inactive_routes.remove(&id) {
+                Some(_) => Ok(()),
+                None => Err(JsValue::from_str(&format!("No route with ID {id}"))),
+            };
+        }
+        if let Some(next) = self.inactive_routes.keys().next().cloned() {
+            self.activate_route(next);
+            self.inactive_routes.remove(&id);
         } else {
-            let linestring = self.entire_line_string()?;
-            let length = linestring.length(&Haversine);
-            let mut f = Feature::from(Geometry::from(&linestring));
-            f.set_property("length_meters", length);
+            self.reset_active_route();
+        }
+        Ok(())
+    }
 
-            let from_name = self.name_waypoint(&self.route.waypoints[0]);
-            let to_name = self.name_waypoint(self.route.waypoints.last().as_ref().unwrap());
-            f.set_property("route_name", format!("Route from {from_name} to {to_name}"));
+    #[wasm_bindgen(js_name = activeRoute)]
+    pub fn active_route(&self) -> u32 {
+        self.active_route
+    }
 
-            let mut full_path = Vec::new();
-            for entry in &self.route.full_path {
-                match entry {
-                    PathEntry::SnappedPoint(node) => {
-                        full_path.push(
-                            serde_json::to_value(&JsonNode {
-                                snapped: Some(node.0),
-                                free: None,
-                            })
-                            .unwrap(),
-                        );
-                    }
-                    PathEntry::FreePoint(pt) => {
-                        full_path.push(
-                            serde_json::to_value(&JsonNode {
-                                snapped: None,
-                                free: Some([trim_lon_lat(pt.x), trim_lon_lat(pt.y)]),
-                            })
-                            .unwrap(),
-                        );
-                    }
-                    PathEntry::Edge(_) => {}
-                }
+    /// Returns the IDs of all routes, in increasing order.
+    #[wasm_bindgen(js_name = routeIds)]
+    pub fn route_ids(&self) -> Vec<u32> {
+        let mut ids: Vec<u32> = self.inactive_routes.keys().cloned().collect();
+        ids.push(self.active_route);
+        ids.sort();
+        ids
+    }
+
+    #[wasm_bindgen(js_name = toFinalFeature)]
+    pub fn to_final_feature(&self) -> Option<String> {
+        let result = self.route_result(&self.route)?;
+        Some(serde_json::to_string_pretty(&export::to_feature(&result)).unwrap())
+    }
+
+    /// Exports finished routes in another format: `geojson` for a FeatureCollection, `polyline5`
+    /// or `polyline6` for a JSON array of Google encoded polylines with that precision, `kml`,
+    /// `flatgeobuf`, or `gpx`. Every format keeps the same properties as `toFinalFeature`. If
+    /// `all_routes` is false, only the active route is exported; otherwise every route is, ordered
+    /// by ID. Unfinished routes are skipped.
+    #[wasm_bindgen(js_name = exportRoutes)]
+    pub fn export_routes(&self, format: &str, all_routes: bool) -> Result<Vec<u8>, JsValue> {
+        let format = export::Format::parse(format).map_err(err_to_js)?;
+        let mut routes = vec![(self.active_route, &self.route)];
+        if all_routes {
+            for (id, stored) in &self.inactive_routes {
+                routes.push((*id, &stored.route));
             }
-            full_path.dedup();
-            f.set_property("full_path", serde_json::Value::Array(full_path));
+            routes.sort_by_key(|(id, _)| *id);
+        }
+        let results: Vec<export::RouteResult> = routes
+            .into_iter()
+            .filter_map(|(_, route)| self.route_result(route))
+            .collect();
+        Ok(export::write(format, &results))
+    }
 
-            f
-        };
+    /// Returns the current route as a GPX file, or nothing if the route isn't finished. Waypoints
+    /// are route points with names, and the full path is a track. The properties from
+    /// `toFinalFeature` are extensions of the route. For areas, the track has a segment for every
+    /// ring, and the route points come from the first ring.
+    #[wasm_bindgen(js_name = toGpx)]
+    pub fn to_gpx(&self) -> Option<String> {
+        let result = self.route_result(&self.route)?;
+        Some(export::to_gpx(&[result]))
+    }
 
-        // Set these on both LineStrings and Polygons
-        let mut waypoints = Vec::new();
-        for waypt in &self.route.waypoints {
-            let pt = unhash_pt(self.to_pt(*waypt));
-            waypoints.push(
-                serde_json::to_value(&RouteWaypoint {
-                    lon: trim_lon_lat(pt.x),
-                    lat: trim_lon_lat(pt.y),
-                    snapped: matches!(waypt, Waypoint::Snapped(_)),
-                })
-                .unwrap(),
-            );
+    /// Replaces the active route with the route points from a GPX file, or its waypoints if there
+    /// are no route points. Points within `snap_distance_meters` of a node snap to it, and the rest
+    /// become freehand points.
+    #[wasm_bindgen(js_name = importGpx)]
+    pub fn import_gpx(&mut self, input: String, snap_distance_meters: f64) -> Result<(), JsValue> {
+        self.try_import_gpx(&input, snap_distance_meters)
+            .map_err(err_to_js)
+    }
+
+    /// Replaces the active route by map-matching a GPS trace onto the network. The input is a GPX
+    /// file with a track, or a GeoJSON LineString. `gps_accuracy_meters` is the typical error of
+    /// the trace points. The route uses as few snapped waypoints as possible, plus freehand
+    /// waypoints where the trace leaves the network. Returns JSON describing the match quality:
+    /// `matched_fraction`, `mean_distance_meters` and `max_distance_meters` from the trace to the
+    /// route (null if there's no route line), `trace_length_meters`, `route_length_meters`, and
+    /// `num_waypoints`. Only works in route mode.
+    #[wasm_bindgen(js_name = matchTrace)]
+    pub fn match_trace(
+        &mut self,
+        input: String,
+        gps_accuracy_meters: f64,
+    ) -> Result<String, JsValue> {
+        if self.router.config.area_mode {
+            return Err(JsValue::from_str("Can't match a trace in area mode"));
+        }
+        if !gps_accuracy_meters.is_finite() || gps_accuracy_meters <= 0.0 {
+            return Err(JsValue::from_str(
+                "gps_accuracy_meters must be a positive number",
+            ));
+        }
+        let trace = matching::read_trace(&input).map_err(err_to_js)?;
+        if trace.len() < 2 {
+            return Err(JsValue::from_str("A trace needs at least two points"));
         }
-        feature.set_property("waypoints", serde_json::Value::Array(waypoints));
 
-        Some(serde_json::to_string_pretty(&feature).unwrap())
+        let result = matching::match_trace(
+            &self.router,
+            &self.edge_segments,
+            &trace,
+            gps_accuracy_meters,
+        );
+        self.reset_active_route();
+        self.route.restore(&self.router, vec![result.waypoints]);
+
+        let line = self.entire_line_string(&self.route);
+        let quality = matching::quality(
+            &trace,
+            line.as_ref(),
+            result.matched_fraction,
+            self.route.waypoints.len(),
+        );
+        Ok(serde_json::to_string_pretty(&quality).unwrap())
+    }
+
+    /// Returns turn-by-turn directions for the current route as a JSON array of steps. Each step
+    /// has a `maneuver`, the street `name`, `length_meters`, `turn_degrees` (positive is to the
+    /// right), the `point` where it begins, and an English `instruction`. The last step is
+    /// `arrive`. Empty if there's no route.
+    #[wasm_bindgen(js_name = getDirections)]
+    pub fn get_directions(&self) -> String {
+        let steps = directions::directions(
+            &self.router.map,
+            &self.route.full_path,
+            self.naming.language.as_deref(),
+        );
+        serde_json::to_string_pretty(&steps).unwrap()
     }
 
     #[wasm_bindgen(js_name = renderGeojson)]
@@ -326,15 +705,48 @@ impl JsRouteSnapper {
         let mut draw_circles: BTreeMap<HashedPoint, (&'static str, Option<String>)> =
             BTreeMap::new();
 
+        // Draw other routes first, so the active one is on top
+        for (id, stored) in &self.inactive_routes {
+            let mut features: Vec<Feature> = stored
+                .route
+                .all_rings()
+                .flat_map(|ring| self.line_string_broken_down(ring))
+                .collect();
+            if self.router.config.area_mode {
+                features.extend(self.into_polygon_area(&stored.route).map(Feature::from));
+            }
+            for mut f in features {
+                f.set_property("inactive", true);
+                f.set_property("route_id", *id);
+                result.push(f);
+            }
+        }
+
         // Draw the confirmed route
-        result.extend(self.line_string_broken_down());
-        for entry in &self.route.full_path {
+        for ring in self.route.all_rings() {
+            for mut f in self.line_string_broken_down(ring) {
+                f.set_property("route_id", self.active_route);
+                result.push(f);
+            }
+        }
+        if let Some(ref alternatives) = self.alternatives {
+            for (idx, path) in alternatives.paths.iter().enumerate() {
+                if let Some(linestring) = path_to_line_string(&self.router.map, path) {
+                    let mut f = Feature::from(Geometry::from(&linestring));
+                    f.set_property("snapped", true);
+                    f.set_property("alternative", idx);
+                    f.set_property("length_meters", linestring.length(&Haversine));
+                    result.push(f);
+                }
+            }
+        }
+        for entry in self.route.all_rings().flat_map(|ring| &ring.full_path) {
             // Every free point is a waypoint, so just handle it below
             if let PathEntry::SnappedPoint(node) = entry {
                 draw_circles.insert(hash_pt(self.router.map.node(*node)), ("node", None));
             }
         }
-        for waypt in &self.route.waypoints {
+        for waypt in self.route.all_rings().flat_map(|ring| &ring.waypoints) {
             draw_circles.insert(
                 self.to_pt(*waypt),
                 (waypt.to_color_name(), Some(self.name_waypoint(waypt))),
@@ -394,6 +806,9 @@ impl JsRouteSnapper {
                 }
             }
         }
+        if let Mode::HoveringLine { pt, .. } = self.mode {
+            draw_circles.insert(hash_pt(pt), ("node", None));
+        }
         if let Mode::Dragging { at, .. } = self.mode {
             draw_circles.insert(
                 self.to_pt(at),
@@ -430,6 +845,7 @@ impl JsRouteSnapper {
         let hovering_pt = match self.mode {
             Mode::Neutral => None,
             Mode::Hovering(pt) => Some(self.to_pt(pt)),
+            Mode::HoveringLine { pt, .. } => Some(hash_pt(pt)),
             Mode::Dragging { at, .. } => Some(self.to_pt(at)),
             Mode::Freehand(pt) => Some(hash_pt(pt)),
         };
@@ -441,24 +857,50 @@ impl JsRouteSnapper {
                 f.set_property("hovered", true);
             }
             if let Some(name) = maybe_name {
-                // Skip freehand points
-                if name != "???" {
+                // Skip freehand points, and nodes where nothing is named
+                if name != self.naming.freehand_point && name != self.naming.unnamed_street {
                     f.set_property("name", name);
                 }
             }
             result.push(f);
         }
 
-        // A polygon for the area
+        // Straight lines where pathfinding failed
+        for (node1, node2) in self.disconnected_legs(&self.route) {
+            let reason = self.router.disconnected_reason(node1, node2);
+            let pt1 = self.to_pt(Waypoint::Snapped(node1));
+            let pt2 = self.to_pt(Waypoint::Snapped(node2));
+            let mut f = Feature::from(Geometry::from(&LineString::new(vec![
+                unhash_pt(pt1),
+                unhash_pt(pt2),
+            ])));
+            f.set_property("disconnected", reason);
+            f.set_property("route_id", self.active_route);
+            result.push(f);
+        }
+
+        // A polygon for the area, and anything making it invalid
+        let mut area_valid = true;
         if self.router.config.area_mode {
-            if let Some(polygon) = self.into_polygon_area() {
-                result.push(Feature::from(polygon));
+            if let Some(polygon) = self.into_polygon_area(&self.route) {
+                let mut f = Feature::from(polygon);
+                f.set_property("route_id", self.active_route);
+                result.push(f);
+            }
+            for (problem, lines) in self.area_problems(&self.route) {
+                area_valid = false;
+                for line in lines {
+                    let mut f = Feature::from(Geometry::from(&LineString::from(line)));
+                    f.set_property("invalid", problem.describe());
+                    f.set_property("route_id", self.active_route);
+                    result.push(f);
+                }
             }
         }
 
         let cursor = match self.mode {
             Mode::Neutral => "inherit",
-            Mode::Hovering(_) => "pointer",
+            Mode::Hovering(_) | Mode::HoveringLine { .. } => "pointer",
             Mode::Dragging { .. } => "grabbing",
             Mode::Freehand(_) => "crosshair",
         };
@@ -469,7 +911,10 @@ impl JsRouteSnapper {
                 serde_json::json!({
                     "cursor": cursor,
                     "snap_mode": self.snap_mode,
-                    "undo_length": self.previous_states.len(),
+                    "undo_length": self.history.undo_length(),
+                    "redo_length": self.history.redo_length(),
+                    "active_route": self.active_route,
+                    "area_valid": area_valid,
                 })
                 .as_object()
                 .unwrap()
@@ -481,15 +926,11 @@ impl JsRouteSnapper {
 
     #[wasm_bindgen(js_name = toggleSnapMode)]
     pub fn toggle_snap_mode(&mut self) {
-        // Can't do this in area mode yet
-        if self.router.config.area_mode {
-            return;
-        }
         self.snap_mode = !self.snap_mode;
 
         // Based on the current mode, immediately change something
         match self.mode {
-            Mode::Neutral => {}
+            Mode::Neutral | Mode::HoveringLine { .. } => {}
             Mode::Hovering(waypt) => {
                 // Are we appending a snapped node?
                 // TODO This repeats logic from on_click to figure out if this is a new node. Split
@@ -541,6 +982,28 @@ impl JsRouteSnapper {
             return true;
         }
 
+        // Grabbing the route's line takes priority over snapping to nodes, unless one is close
+        // enough to click
+        if !matches!(self.mode, Mode::Dragging { .. }) {
+            if let Some((full_idx, line_pt)) = self.mouseover_route_line(pt, circle_radius_meters) {
+                let near_waypt = self
+                    .mouseover_something(pt, circle_radius_meters)
+                    .filter(|waypt| self.can_hover(*waypt))
+                    .is_some_and(|waypt| {
+                        Haversine
+                            .distance(Point::from(unhash_pt(self.to_pt(waypt))), Point::from(pt))
+                            < circle_radius_meters
+                    });
+                if !near_waypt {
+                    self.mode = Mode::HoveringLine {
+                        full_idx,
+                        pt: line_pt,
+                    };
+                    return true;
+                }
+            }
+        }
+
         let mut changed = false;
         match self.mode {
             // If we were just in freehand mode and we released the key, go back to snapping
@@ -550,7 +1013,7 @@ impl JsRouteSnapper {
                     changed = true;
                 }
             }
-            Mode::Hovering(_) => {
+            Mode::Hovering(_) | Mode::HoveringLine { .. } => {
                 if let Some(waypt) = self.mouseover_something(pt, circle_radius_meters) {
                     self.mode = Mode::Hovering(waypt);
                 } else {
@@ -562,18 +1025,27 @@ impl JsRouteSnapper {
                 // Keep the same snapped/free type here. Toggling will change this current
                 // waypoint.
                 let new_waypt = match at {
-                    Waypoint::Snapped(_) => self.mouseover_node(pt).map(Waypoint::Snapped),
+                    Waypoint::Snapped(_) => self.snappable_node(pt).map(Waypoint::Snapped),
                     Waypoint::Free(_) => Some(Waypoint::Free(pt)),
                 };
                 if let Some(new_waypt) = new_waypt {
                     if new_waypt != at {
+                        let before = self.refuse_disconnected.then(|| self.route.clone());
                         // Don't keep every single update during a drag
                         let new_idx = self.route.move_waypoint(&self.router, idx, new_waypt);
-                        self.mode = Mode::Dragging {
-                            idx: new_idx,
-                            at: new_waypt,
-                        };
-                        changed = true;
+                        if before.as_ref().is_some_and(|before| {
+                            self.disconnected_legs(&self.route).len()
+                                > self.disconnected_legs(before).len()
+                        }) {
+                            // Stay where we were
+                            self.route = before.unwrap();
+                        } else {
+                            self.mode = Mode::Dragging {
+                                idx: new_idx,
+                                at: new_waypt,
+                            };
+                            changed = true;
+                        }
                     }
                 }
             }
@@ -588,45 +1060,63 @@ impl JsRouteSnapper {
 
     // Can we add new points to the end of the route right now?
     fn can_extend_route(&self) -> bool {
+        // Once an area is closed, new points can only be added by dragging
+        if self.router.config.area_mode && self.route.is_closed_area() {
+            return false;
+        }
         self.route.waypoints.len() < 2 || self.router.config.extend_route
     }
 
     // If we shouldn't extend the route right now, then only allow hovering on a point already in
     // the route (for dragging it). Don't hover on any new points.
     fn dont_hover_new_points(&mut self) {
-        if !self.can_extend_route() {
-            if let Mode::Hovering(waypt) = self.mode {
-                if !self.route.full_path.contains(&waypt.to_path_entry()) {
-                    // We're not dragging
-                    self.mode = Mode::Neutral;
-                }
+        if let Mode::Hovering(waypt) = self.mode {
+            if !self.can_hover(waypt) {
+                // We're not dragging
+                self.mode = Mode::Neutral;
             }
         }
     }
 
+    fn can_hover(&self, waypt: Waypoint) -> bool {
+        self.can_extend_route()
+            || self.route.contains(&waypt.to_path_entry())
+            || self
+                .inactive_route_containing(waypt.to_path_entry())
+                .is_some()
+    }
+
     #[wasm_bindgen(js_name = onClick)]
     pub fn on_click(&mut self) {
-        // TODO Allow freehand points for areas, once we can convert existing waypoints
-        if !self.router.config.area_mode {
-            if let Mode::Freehand(pt) = self.mode {
+        if let Mode::Freehand(pt) = self.mode {
+            if self.can_extend_route() {
                 self.before_update();
                 self.route.add_waypoint(&self.router, Waypoint::Free(pt));
+                self.maybe_close_area();
             }
         }
 
         if let Mode::Hovering(hover) = self.mode {
+            if self.route.focus_ring_containing(&hover.to_path_entry()) {
+                self.alternatives = None;
+            }
+
             if let Some(idx) = self.route.waypoints.iter().position(|x| *x == hover) {
                 // Click an existing waypoint to delete it
                 if self.route.is_closed_area() {
                     // Don't go below 2 waypoints (+1 because first=last)
-                    // TODO Allow deleting the special first=last waypoint
-                    if self.route.waypoints.len() > 3
-                        && idx != 0
-                        && idx != self.route.waypoints.len() - 1
-                    {
+                    if self.route.waypoints.len() > 3 {
                         self.before_update();
-                        self.route.waypoints.remove(idx);
+                        if idx == 0 || idx == self.route.waypoints.len() - 1 {
+                            // Re-close the area on the next waypoint
+                            self.route.waypoints.remove(0);
+                            self.route.waypoints.pop();
+                            self.route.waypoints.push(self.route.waypoints[0]);
+                        } else {
+                            self.route.waypoints.remove(idx);
+                        }
                         self.route.recalculate_full_path(&self.router);
+                        self.dont_hover_new_points();
                     }
                 } else {
                     // Don't delete the only waypoint
@@ -645,33 +1135,121 @@ impl JsRouteSnapper {
                 if self.route.full_path.contains(&hover.to_path_entry()) {
                     return;
                 }
+                if self.would_disconnect(hover) {
+                    return;
+                }
 
                 self.before_update();
                 self.route.add_waypoint(&self.router, hover);
-                if self.router.config.area_mode
-                    && !self.route.is_closed_area()
-                    && self.route.waypoints.len() == 3
-                {
-                    // Close off the area
-                    self.route
-                        .add_waypoint(&self.router, self.route.waypoints[0]);
-                }
+                self.maybe_close_area();
+            }
+        }
+    }
+
+    // True if appending this waypoint should be refused, because it can't be reached from the
+    // last one. In area mode, also checks the way back to the start, if the area would be closed.
+    fn would_disconnect(&self, waypt: Waypoint) -> bool {
+        if !self.refuse_disconnected {
+            return false;
+        }
+        let mut pairs = Vec::new();
+        if let Some(last) = self.route.waypoints.last() {
+            pairs.push((*last, waypt));
+        }
+        if self.router.config.area_mode && self.route.waypoints.len() == 2 {
+            pairs.push((waypt, self.route.waypoints[0]));
+        }
+        pairs.into_iter().any(|pair| match pair {
+            (Waypoint::Snapped(node1), Waypoint::Snapped(node2)) => {
+                node1 != node2 && self.router.pathfind(node1, node2, &Vec::new()).is_none()
             }
+            _ => false,
+        })
+    }
+
+    // Call after adding the third waypoint to an area
+    fn maybe_close_area(&mut self) {
+        if self.router.config.area_mode
+            && !self.route.is_closed_area()
+            && self.route.waypoints.len() == 3
+        {
+            // Close off the area. Since before_update was only called once, this is still one step
+            // to undo.
+            self.route
+                .add_waypoint(&self.router, self.route.waypoints[0]);
         }
     }
 
     // True if we should hijack the drag controls
     #[wasm_bindgen(js_name = onDragStart)]
     pub fn on_drag_start(&mut self) -> bool {
+        if let Mode::HoveringLine { full_idx, pt } = self.mode {
+            // Insert a new waypoint here, then drag it. Only snap when grabbing part of the
+            // network.
+            let snapped =
+                self.snap_mode && matches!(self.route.full_path[full_idx], PathEntry::Edge(_));
+            let new_waypt = if snapped {
+                match self.mouseover_node(pt) {
+                    Some(node) => Waypoint::Snapped(node),
+                    None => return false,
+                }
+            } else {
+                Waypoint::Free(pt)
+            };
+            // The whole drag is one step to undo, and only if something changes
+            self.alternatives = None;
+            self.history.start_group(&self.route.snapshot());
+
+            // If the nearest node is already on the route, drag it. It might be on the route more
+            // than once, so use the occurrence closest to the line being dragged.
+            let existing = self
+                .route
+                .full_path
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| **entry == new_waypt.to_path_entry())
+                .map(|(idx, _)| idx)
+                .min_by_key(|idx| idx.abs_diff(full_idx));
+            let idx = match existing {
+                Some(idx) => idx,
+                None => {
+                    let way_idx = self
+                        .route
+                        .waypoint_indices()
+                        .into_iter()
+                        .filter(|idx| *idx <= full_idx)
+                        .count();
+                    self.route.waypoints.insert(way_idx, new_waypt);
+                    self.route.recalculate_full_path(&self.router);
+                    self.route.waypoint_indices()[way_idx]
+                }
+            };
+            self.mode = Mode::Dragging { idx, at: new_waypt };
+            self.snap_mode = snapped;
+            return true;
+        }
+
         if let Mode::Hovering(at) = self.mode {
+            // Dragging a point on another route or ring switches to editing it
+            if !self.route.contains(&at.to_path_entry()) {
+                if let Some(id) = self.inactive_route_containing(at.to_path_entry()) {
+                    self.activate_route(id);
+                    self.mode = Mode::Hovering(at);
+                }
+            }
+            if self.route.focus_ring_containing(&at.to_path_entry()) {
+                self.alternatives = None;
+            }
+
             if let Some(idx) = self
                 .route
                 .full_path
                 .iter()
                 .position(|x| *x == at.to_path_entry())
             {
-                // TODO Only do this for the first actual bit of drag?
-                self.before_update();
+                // The whole drag is one step to undo, and only if something changes
+                self.alternatives = None;
+                self.history.start_group(&self.route.snapshot());
                 self.mode = Mode::Dragging { idx, at };
                 self.snap_mode = matches!(at, Waypoint::Snapped(_));
                 return true;
@@ -684,44 +1262,152 @@ impl JsRouteSnapper {
     #[wasm_bindgen(js_name = onMouseUp)]
     pub fn on_mouse_up(&mut self) -> bool {
         if let Mode::Dragging { at, .. } = self.mode {
+            self.history.end_group(&self.route.snapshot());
             self.mode = Mode::Hovering(at);
             return true;
         }
         false
     }
 
-    /// Note this doesn't change route/area mode.
+    /// Clears the active route and deletes all other routes. Note this doesn't change route/area
+    /// mode.
     #[wasm_bindgen(js_name = clearState)]
     pub fn clear_state(&mut self) {
-        self.route = Route::new();
-        self.mode = Mode::Neutral;
-        self.snap_mode = true;
-        self.previous_states.clear();
+        self.reset_active_route();
+        self.inactive_routes.clear();
     }
 
+    /// Replaces the active route with a previously created one. Other routes aren't affected.
+    /// For areas with more than one ring, also pass in the `other_rings` property.
     #[wasm_bindgen(js_name = editExisting)]
-    pub fn edit_existing(&mut self, raw_waypoints: JsValue) -> Result<(), JsValue> {
-        self.clear_state();
+    pub fn edit_existing(
+        &mut self,
+        raw_waypoints: JsValue,
+        raw_other_rings: JsValue,
+    ) -> Result<(), JsValue> {
+        let mut rings: Vec<Vec<RouteWaypoint>> =
+            vec![serde_wasm_bindgen::from_value(raw_waypoints)?];
+        if !raw_other_rings.is_undefined() && !raw_other_rings.is_null() {
+            let other_rings: Vec<Vec<RouteWaypoint>> =
+                serde_wasm_bindgen::from_value(raw_other_rings)?;
+            rings.extend(other_rings);
+        }
+        self.load_rings(rings)
+    }
 
-        let waypoints: Vec<RouteWaypoint> = serde_wasm_bindgen::from_value(raw_waypoints)?;
+    /// Returns the entire editing state as JSON, including waypoints, configuration, area mode,
+    /// snap mode, and undo/redo history. Changes to the graph itself aren't included.
+    #[wasm_bindgen(js_name = exportSession)]
+    pub fn export_session(&self) -> String {
+        let to_json = |rings: &Vec<Vec<Waypoint>>| {
+            rings
+                .iter()
+                .map(|waypoints| {
+                    waypoints
+                        .iter()
+                        .map(|waypt| self.encode_waypoint(*waypt))
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut routes = vec![(self.active_route, &self.route, &self.history)];
+        for (id, stored) in &self.inactive_routes {
+            routes.push((*id, &stored.route, &stored.history));
+        }
+        routes.sort_by_key(|(id, _, _)| *id);
+
+        let session = Session {
+            version: SESSION_VERSION,
+            config: Config {
+                avoid_doubling_back: self.router.config.avoid_doubling_back,
+                extend_route: self.router.config.extend_route,
+                area_mode: false,
+            },
+            area_mode: self.router.config.area_mode,
+            snap_mode: self.snap_mode,
+            repair_areas: self.repair_areas,
+            refuse_disconnected: self.refuse_disconnected,
+            snap_within_component: self.snap_within_component,
+            naming: self.naming.clone(),
+            active_route: self.active_route,
+            routes: routes
+                .into_iter()
+                .map(|(id, route, history)| SessionRoute {
+                    id,
+                    rings: to_json(&route.snapshot()),
+                    undo: history.undo_stack().map(to_json).collect(),
+                    redo: history.redo_stack().iter().map(to_json).collect(),
+                })
+                .collect(),
+        };
+        serde_json::to_string(&session).unwrap()
+    }
 
-        for waypt in waypoints {
-            let pt = Coord {
-                x: waypt.lon,
-                y: waypt.lat,
-            };
-            if waypt.snapped {
-                if let Some(node) = self.mouseover_node(pt) {
-                    self.route
-                        .add_waypoint(&self.router, Waypoint::Snapped(node));
-                } else {
-                    return Err(JsValue::from_str("A waypoint didn't snap"));
-                }
-            } else {
-                self.route.add_waypoint(&self.router, Waypoint::Free(pt));
-            }
+    /// Restores state from `exportSession`, replacing everything currently happening, including
+    /// all routes. The same graph must be loaded. The caller should redraw.
+    #[wasm_bindgen(js_name = importSession)]
+    pub fn import_session(&mut self, input: String) -> Result<(), JsValue> {
+        let session: Session = serde_json::from_str(&input).map_err(err_to_js)?;
+        if session.version != SESSION_VERSION {
+            return Err(JsValue::from_str(&format!(
+                "Unsupported session version {}",
+                session.version
+            )));
+        }
+        if !session.routes.iter().any(|r| r.id == session.active_route) {
+            return Err(JsValue::from_str("The active route is missing"));
+        }
+        let from_json = |rings: Vec<Vec<RouteWaypoint>>| {
+            rings
+                .iter()
+                .map(|waypoints| {
+                    waypoints
+                        .iter()
+                        .map(|waypt| self.decode_waypoint(waypt))
+                        .collect::<Result<Vec<_>, JsValue>>()
+                })
+                .collect::<Result<Vec<_>, JsValue>>()
+        };
+        let mut routes = BTreeMap::new();
+        for r in session.routes {
+            let mut route = Route::new();
+            route.restore(&self.router, from_json(r.rings)?);
+            let undo = r
+                .undo
+                .into_iter()
+                .map(from_json)
+                .collect::<Result<Vec<_>, JsValue>>()?;
+            let redo = r
+                .redo
+                .into_iter()
+                .map(from_json)
+                .collect::<Result<Vec<_>, JsValue>>()?;
+            routes.insert(
+                r.id,
+                StoredRoute {
+                    route,
+                    history: History::from_stacks(undo, redo),
+                },
+            );
         }
 
+        self.clear_state();
+        self.router.config = Config {
+            area_mode: session.area_mode,
+            ..session.config
+        };
+        self.next_route_id = routes.keys().max().unwrap() + 1;
+        self.inactive_routes = routes;
+        let active = self.inactive_routes.remove(&session.active_route).unwrap();
+        self.route = active.route;
+        self.history = active.history;
+        self.active_route = session.active_route;
+        self.snap_mode = session.snap_mode;
+        self.repair_areas = session.repair_areas;
+        self.refuse_disconnected = session.refuse_disconnected;
+        self.snap_within_component = session.snap_within_component;
+        self.naming = session.naming;
+        self.recalculate_all_routes();
         Ok(())
     }
 
@@ -735,14 +1421,28 @@ impl JsRouteSnapper {
             f.set_property("node1", edge.node1.0);
             f.set_property("node2", edge.node2.0);
             f.set_property("length_meters", edge.length_meters);
-            f.set_property("forward_cost", edge.forward_cost);
-            f.set_property("backward_cost", edge.backward_cost);
+            // Show the current costs, including any overrides or zones
+            let id = EdgeID(idx as u32);
+            f.set_property(
+                "forward_cost",
+                self.router.effective_cost(DirectedEdge(id, FORWARDS)),
+            );
+            f.set_property(
+                "backward_cost",
+                self.router.effective_cost(DirectedEdge(id, BACKWARDS)),
+            );
             f.set_property("name", edge.name.clone());
+            // Edges between components aren't part of either
+            let component = self.router.components[edge.node1.0 as usize];
+            if component == self.router.components[edge.node2.0 as usize] {
+                f.set_property("component", component.0);
+            }
             features.push(f);
         }
         for (idx, pt) in self.router.map.nodes.iter().enumerate() {
             let mut f = Feature::from(Geometry::from(&Point::from(*pt)));
             f.set_property("node_id", idx);
+            f.set_property("component", self.router.components[idx].0);
             features.push(f);
         }
         let gj =
@@ -767,7 +1467,7 @@ impl JsRouteSnapper {
         let waypoints: Vec<RouteWaypoint> = serde_wasm_bindgen::from_value(raw_waypoints)?;
         let from_name = self.name_for_waypoint(&waypoints[0])?;
         let to_name = self.name_for_waypoint(waypoints.last().unwrap())?;
-        Ok(format!("Route from {from_name} to {to_name}"))
+        Ok(self.naming.route_name(&from_name, &to_name, Vec::new()))
     }
 
     #[wasm_bindgen(js_name = addSnappedWaypoint)]
@@ -777,7 +1477,10 @@ impl JsRouteSnapper {
             return;
         }
         let pt = Coord { x: lon, y: lat };
-        if let Some(node) = self.mouseover_node(pt) {
+        if let Some(node) = self.snappable_node(pt) {
+            if self.would_disconnect(Waypoint::Snapped(node)) {
+                return;
+            }
             self.before_update();
             self.route
                 .add_waypoint(&self.router, Waypoint::Snapped(node));
@@ -790,21 +1493,217 @@ impl JsRouteSnapper {
             // Too confusing
             return;
         }
-        if let Some(state) = self.previous_states.pop() {
-            self.route.waypoints = state;
+        if let Some(state) = self.history.undo(&self.route.snapshot()) {
+            self.route.restore(&self.router, state);
+            self.alternatives = None;
+        }
+    }
+
+    #[wasm_bindgen()]
+    pub fn redo(&mut self) {
+        if let Mode::Dragging { .. } = self.mode {
+            return;
+        }
+        if let Some(state) = self.history.redo(&self.route.snapshot()) {
+            self.route.restore(&self.router, state);
+            self.alternatives = None;
+        }
+    }
+
+    /// Finds up to `k` alternative routes for the leg starting at waypoint `leg`. Both waypoints
+    /// of the leg must be snapped. `renderGeojson` will show the alternatives until the route
+    /// changes. Returns the number found.
+    #[wasm_bindgen(js_name = previewAlternatives)]
+    pub fn preview_alternatives(&mut self, leg: usize, k: usize) -> Result<usize, JsValue> {
+        self.alternatives = None;
+        if leg + 1 >= self.route.waypoints.len() {
+            return Err(JsValue::from_str("No leg starts at that waypoint"));
+        }
+        let (Waypoint::Snapped(node1), Waypoint::Snapped(node2)) =
+            (self.route.waypoints[leg], self.route.waypoints[leg + 1])
+        else {
+            return Err(JsValue::from_str(
+                "Both waypoints of the leg must be snapped",
+            ));
+        };
+        let indices = self.route.waypoint_indices();
+        let current = &self.route.full_path[indices[leg]..=indices[leg + 1]];
+        if !current.iter().any(|x| matches!(x, PathEntry::Edge(_))) {
+            return Err(JsValue::from_str("The leg isn't connected by the graph"));
+        }
+
+        let paths = self.router.alternatives(node1, node2, current, k);
+        let num = paths.len();
+        self.alternatives = Some(Alternatives { leg, paths });
+        Ok(num)
+    }
+
+    /// Changes the route to follow one of the alternatives from `previewAlternatives`, by adding
+    /// shaping waypoints to the leg.
+    #[wasm_bindgen(js_name = pinAlternative)]
+    pub fn pin_alternative(&mut self, idx: usize) -> Result<(), JsValue> {
+        let Some(alternatives) = self.alternatives.take() else {
+            return Err(JsValue::from_str("No alternatives are being previewed"));
+        };
+        let Some(path) = alternatives.paths.get(idx) else {
+            return Err(JsValue::from_str("No alternative with that index"));
+        };
+        let leg = alternatives.leg;
+        self.before_update();
+
+        let goal: Vec<NodeID> = path.iter().flat_map(|x| x.to_snapped_node()).collect();
+        let prefix = self.route.waypoints[..=leg].to_vec();
+        let suffix = self.route.waypoints[leg + 1..].to_vec();
+        // Shaping waypoints and their index in goal
+        let mut shaping: Vec<(usize, NodeID)> = Vec::new();
+
+        for _ in 0..MAX_SHAPING_ATTEMPTS {
+            let indices = self.route.waypoint_indices();
+            let mut current: Vec<NodeID> = self.route.full_path
+                [indices[leg]..=indices[leg + shaping.len() + 1]]
+                .iter()
+                .flat_map(|x| x.to_snapped_node())
+                .collect();
+            // full_path sometimes repeats nodes around waypoints
+            current.dedup();
+            if current == goal {
+                break;
+            }
+
+            // Shape the route using the middle of every stretch where it doesn't match
+            let current: HashSet<NodeID> = current.into_iter().collect();
+            let mut stretch_start = None;
+            let mut added = false;
+            for (goal_idx, node) in goal.iter().enumerate() {
+                if !current.contains(node) {
+                    stretch_start.get_or_insert(goal_idx);
+                } else if let Some(start) = stretch_start.take() {
+                    let middle = (start + goal_idx - 1) / 2;
+                    shaping.push((middle, goal[middle]));
+                    added = true;
+                }
+            }
+            if !added {
+                break;
+            }
+            shaping.sort();
+
+            self.route.waypoints = prefix.clone();
+            self.route
+                .waypoints
+                .extend(shaping.iter().map(|(_, node)| Waypoint::Snapped(*node)));
+            self.route.waypoints.extend(suffix.clone());
             self.route.recalculate_full_path(&self.router);
         }
+
+        Ok(())
     }
 
-    fn name_for_waypoint(&self, waypoint: &RouteWaypoint) -> Result<String, JsValue> {
-        if !waypoint.snapped {
-            return Ok("???".to_string());
+    /// In area mode, finishes the current ring and starts drawing another. A ring inside another
+    /// one becomes a hole; otherwise the result is a MultiPolygon. Points on other rings can still
+    /// be dragged or deleted. The caller should redraw.
+    #[wasm_bindgen(js_name = startNewRing)]
+    pub fn start_new_ring(&mut self) -> Result<(), JsValue> {
+        if !self.router.config.area_mode {
+            return Err(JsValue::from_str("Only areas can have multiple rings"));
+        }
+        if !self.route.is_closed_area() {
+            return Err(JsValue::from_str("Finish the current ring first"));
         }
+        self.before_update();
+        self.route.start_new_ring();
+        self.mode = Mode::Neutral;
+        Ok(())
+    }
 
+    /// Changes which waypoint of a closed area is the first and last, without changing the
+    /// polygon. The caller should redraw.
+    #[wasm_bindgen(js_name = rotateArea)]
+    pub fn rotate_area(&mut self, waypoint_idx: usize) -> Result<(), JsValue> {
+        if !self.route.is_closed_area() {
+            return Err(JsValue::from_str("The area isn't closed"));
+        }
+        // The last waypoint is the same as the first
+        if waypoint_idx >= self.route.waypoints.len() - 1 {
+            return Err(JsValue::from_str("No waypoint with that index"));
+        }
+        if waypoint_idx == 0 {
+            return Ok(());
+        }
+        self.before_update();
+        self.route.rotate_area(waypoint_idx);
+        Ok(())
+    }
+
+    /// In area mode, replaces the area being edited with the smallest block of streets enclosing a
+    /// point, using snapped waypoints at every intersection around it. If `merge` is true and the
+    /// area is already closed, the block is added to it instead. Only blocks sharing a street with
+    /// the area can be merged. The caller should redraw.
+    #[wasm_bindgen(js_name = areaFromPoint)]
+    pub fn area_from_point(&mut self, lon: f64, lat: f64, merge: bool) -> Result<(), JsValue> {
+        if !self.router.config.area_mode {
+            return Err(JsValue::from_str("Only works in area mode"));
+        }
+        if self.face_index.is_none() {
+            self.face_index = Some(FaceIndex::new(&self.router.map));
+        }
+        let index = self.face_index.as_ref().unwrap();
+        let Some(mut face) = faces::enclosing_face(
+            &self.router.map,
+            index,
+            &self.edge_segments,
+            Coord { x: lon, y: lat },
+        ) else {
+            return Err(JsValue::from_str("No block encloses this point"));
+        };
+        if merge && self.route.is_closed_area() {
+            let Some(current) = self.area_as_face() else {
+                return Err(JsValue::from_str(
+                    "Only areas with snapped waypoints can be merged",
+                ));
+            };
+            let Some(merged) = faces::merge_faces(&self.router.map, index, &current, &face) else {
+                return Err(JsValue::from_str(
+                    "The block must share a street with the area",
+                ));
+            };
+            face = merged;
+        }
+
+        // Start from the same place, if possible
+        let mut nodes: Vec<NodeID> = face
+            .iter()
+            .map(|e| faces::src_node(&self.router.map, *e))
+            .collect();
+        if let Some(Waypoint::Snapped(first)) = self.route.waypoints.first() {
+            if let Some(idx) = nodes.iter().position(|n| n == first) {
+                nodes.rotate_left(idx);
+            }
+        }
+        nodes.push(nodes[0]);
+
+        self.before_update();
+        self.route.waypoints = nodes.into_iter().map(Waypoint::Snapped).collect();
+        self.route.recalculate_full_path(&self.router);
+        self.mode = Mode::Neutral;
+        Ok(())
+    }
+
+    /// Stops showing alternatives from `previewAlternatives`.
+    #[wasm_bindgen(js_name = clearAlternatives)]
+    pub fn clear_alternatives(&mut self) {
+        self.alternatives = None;
+    }
+
+    fn name_for_waypoint(&self, waypoint: &RouteWaypoint) -> Result<String, JsValue> {
         let pt = Coord {
             x: waypoint.lon,
             y: waypoint.lat,
         };
+        if !waypoint.snapped {
+            return Ok(self.name_waypoint(&Waypoint::Free(pt)));
+        }
+
         if let Some(node) = self.mouseover_node(pt) {
             Ok(self.name_waypoint(&Waypoint::Snapped(node)))
         } else {
@@ -812,12 +1711,113 @@ impl JsRouteSnapper {
         }
     }
 
-    fn before_update(&mut self) {
-        self.previous_states.push(self.route.waypoints.clone());
-        // TODO Different data structure to make this more efficient
-        if self.previous_states.len() > MAX_PREVIOUS_STATES {
-            self.previous_states.remove(0);
+    fn check_edge_id(&self, edge_id: u32) -> Result<(), JsValue> {
+        if edge_id as usize >= self.router.map.edges.len() {
+            return Err(JsValue::from_str("Unknown edge ID"));
+        }
+        Ok(())
+    }
+
+    fn after_cost_change(&mut self) {
+        self.router.update_components();
+        // Any alternatives were calculated with the old costs
+        self.alternatives = None;
+        self.recalculate_all_routes();
+    }
+
+    fn recalculate_all_routes(&mut self) {
+        self.route.recalculate_all_rings(&self.router);
+        for stored in self.inactive_routes.values_mut() {
+            stored.route.recalculate_all_rings(&self.router);
+        }
+    }
+
+    fn reset_active_route(&mut self) {
+        self.route = Route::new();
+        self.mode = Mode::Neutral;
+        self.snap_mode = true;
+        self.history = History::default();
+        self.alternatives = None;
+    }
+
+    // Moves the active route into inactive_routes, leaving an empty route behind
+    fn stash_active_route(&mut self) {
+        // If a drag is in progress, finish it
+        self.history.end_group(&self.route.snapshot());
+        self.inactive_routes.insert(
+            self.active_route,
+            StoredRoute {
+                route: std::mem::replace(&mut self.route, Route::new()),
+                history: std::mem::take(&mut self.history),
+            },
+        );
+    }
+
+    // The ID must be in inactive_routes
+    fn activate_route(&mut self, id: u32) {
+        let stored = self.inactive_routes.remove(&id).unwrap();
+        self.stash_active_route();
+        self.route = stored.route;
+        self.history = stored.history;
+        self.active_route = id;
+        self.mode = Mode::Neutral;
+        self.alternatives = None;
+    }
+
+    fn inactive_route_containing(&self, entry: PathEntry) -> Option<u32> {
+        self.inactive_routes
+            .iter()
+            .find(|(_, stored)| stored.route.contains(&entry))
+            .map(|(id, _)| *id)
+    }
+
+    // Incrementally update everything derived from the map
+    fn after_graph_change(&mut self, all_changes: Vec<GraphChanges>) {
+        // The map already updated snap_to_nodes and edge_segments
+        for changes in all_changes {
+            for split in changes.split_edges {
+                let node1 = self.router.map.edge(split.edge).node1;
+                for (from, to) in [(node1, split.old_node2), (split.old_node2, node1)] {
+                    // There may be a different parallel edge in the graph
+                    if self.router.graph.edge_weight(from, to).map(|x| x.0) == Some(split.edge) {
+                        self.router.graph.remove_edge(from, to);
+                    }
+                }
+                self.router.add_to_graph(split.edge);
+                self.router.add_to_graph(split.new_edge);
+
+                // Each half may only be in some of the zones. Cost overrides are divided by
+                // length, like the edge's own costs.
+                self.router.apply_zones(split.edge);
+                self.router.apply_zones(split.new_edge);
+                for dir in [FORWARDS, BACKWARDS] {
+                    if let Some(&cost) = self.router.cost_overrides.get(&(split.edge, dir)) {
+                        self.router
+                            .cost_overrides
+                            .insert((split.edge, dir), cost.map(|x| x * split.fraction));
+                        self.router.cost_overrides.insert(
+                            (split.new_edge, dir),
+                            cost.map(|x| x * (1.0 - split.fraction)),
+                        );
+                    }
+                }
+            }
+
+            self.router.add_to_graph(changes.new_edge);
+            self.router.apply_zones(changes.new_edge);
+        }
+        self.face_index = None;
+
+        if self.router.ch.take().is_some() {
+            warn!("The graph changed, so the contraction hierarchy can't be used anymore");
         }
+        self.after_cost_change();
+    }
+
+    fn before_update(&mut self) {
+        // Any alternatives are for the old route
+        self.alternatives = None;
+        self.history.record(&self.route.snapshot());
     }
 
     // TODO If this new style works well, either ditch the old stateful API entirely, or export a
@@ -928,10 +1928,32 @@ impl JsRouteSnapper {
 }
 
 impl JsRouteSnapper {
+    /// Like `importGpx`, but returns errors as strings, so it also works outside of WASM.
+    pub fn try_import_gpx(&mut self, input: &str, snap_distance_meters: f64) -> Result<(), String> {
+        let points = gpx::read_route_points(input)?;
+        let waypoints = points
+            .into_iter()
+            .map(|point| match self.mouseover_node(point.pt) {
+                Some(node)
+                    if Haversine.distance(
+                        Point::from(point.pt),
+                        Point::from(self.router.map.node(node)),
+                    ) <= snap_distance_meters =>
+                {
+                    Waypoint::Snapped(node)
+                }
+                _ => Waypoint::Free(point.pt),
+            })
+            .collect();
+        self.reset_active_route();
+        self.route.restore(&self.router, vec![waypoints]);
+        Ok(())
+    }
+
     // Snaps first to free-drawn points, then nodes
     fn mouseover_something(&self, pt: Coord, circle_radius_meters: f64) -> Option<Waypoint> {
         // TODO For very long routes, this'll get slow
-        for waypt in &self.route.waypoints {
+        for waypt in self.route.all_rings().flat_map(|ring| &ring.waypoints) {
             if let Waypoint::Free(x) = waypt {
                 if Haversine.distance(Point::from(*x), Point::from(pt)) < circle_radius_meters {
                     return Some(*waypt);
@@ -939,59 +1961,116 @@ impl JsRouteSnapper {
             }
         }
 
-        let node = self.mouseover_node(pt)?;
+        for stored in self.inactive_routes.values() {
+            for waypt in &stored.route.waypoints {
+                if let Waypoint::Free(x) = waypt {
+                    if Haversine.distance(Point::from(*x), Point::from(pt)) < circle_radius_meters {
+                        return Some(*waypt);
+                    }
+                }
+            }
+        }
+
+        let node = self.snappable_node(pt)?;
 
-        // If we've closed off an area, don't snap to other nodes
+        // If we've closed off an area, don't snap to other nodes, unless they're on another route
+        // that could be dragged
         if self.route.is_closed_area()
-            && !self
-                .route
-                .full_path
-                .contains(&PathEntry::SnappedPoint(node))
+            && !self.route.contains(&PathEntry::SnappedPoint(node))
+            && self
+                .inactive_route_containing(PathEntry::SnappedPoint(node))
+                .is_none()
         {
             return None;
         }
 
         Some(Waypoint::Snapped(node))
     }
-    fn mouseover_node(&self, pt: Coord) -> Option<NodeID> {
-        let pt = [pt.x, pt.y];
-        let node = self.snap_to_nodes.nearest_neighbor(&pt)?;
-        Some(node.data)
-    }
 
-    fn entire_line_string(&self) -> Option<LineString> {
-        if self.route.full_path.is_empty() {
-            return None;
-        }
-        let mut pts = Vec::new();
+    // If the cursor is on the active route's line, but not close to any point along it, returns
+    // the index into full_path where that piece of the line starts, and the closest point on it
+    fn mouseover_route_line(&self, pt: Coord, circle_radius_meters: f64) -> Option<(usize, Coord)> {
+        let cursor = Point::from(pt);
+        let entry_pt = |entry: &PathEntry| match entry {
+            PathEntry::SnappedPoint(node) => Some(self.router.map.node(*node)),
+            PathEntry::FreePoint(pt) => Some(*pt),
+            PathEntry::Edge(_) => None,
+        };
 
-        for entry in &self.route.full_path {
-            match entry {
-                PathEntry::SnappedPoint(node) => {
-                    // There may be an adjacent Edge that contributes geometry, but maybe not near
-                    // free points. We'll dedupe later anyway.
-                    pts.push(self.router.map.node(*node));
+        // (full_idx, closest point, distance)
+        let mut closest: Option<(usize, Coord, f64)> = None;
+        for (idx, entry) in self.route.full_path.iter().enumerate() {
+            let pts = if let PathEntry::Edge(dir_edge) = entry {
+                edge_geometry(&self.router.map, *dir_edge)
+            } else {
+                let here = entry_pt(entry).unwrap();
+                if Haversine.distance(Point::from(here), cursor) < circle_radius_meters {
+                    return None;
                 }
-                PathEntry::FreePoint(pt) => {
-                    pts.push(*pt);
+                // Two points in a row are joined by a straight line
+                match self.route.full_path.get(idx + 1).and_then(entry_pt) {
+                    Some(next) => vec![here, next],
+                    None => continue,
                 }
-                PathEntry::Edge(dir_edge) => {
-                    pts.extend(edge_geometry(&self.router.map, *dir_edge));
+            };
+
+            for pair in pts.windows(2) {
+                let on_line = match Line::new(pair[0], pair[1]).closest_point(&cursor) {
+                    Closest::Intersection(x) | Closest::SinglePoint(x) => x,
+                    Closest::Indeterminate => continue,
+                };
+                let dist = Haversine.distance(on_line, cursor);
+                if closest.map(|x| dist < x.2).unwrap_or(true) {
+                    closest = Some((idx, on_line.into(), dist));
                 }
             }
         }
 
-        pts.dedup();
-        if pts.len() < 2 {
-            return None;
-        }
-        Some(LineString::new(pts))
+        let (idx, on_line, dist) = closest?;
+        (dist < circle_radius_meters).then_some((idx, on_line))
+    }
+
+    fn mouseover_node(&self, pt: Coord) -> Option<NodeID> {
+        let pt = [pt.x, pt.y];
+        let node = self.snap_to_nodes.nearest_neighbor(&pt)?;
+        Some(node.data)
+    }
+
+    // Like mouseover_node, but for placing a waypoint on the active route. Optionally stays in the
+    // component of the last snapped waypoint.
+    fn snappable_node(&self, pt: Coord) -> Option<NodeID> {
+        let pt = [pt.x, pt.y];
+        let component = self
+            .snap_within_component
+            .then(|| {
+                self.route
+                    .waypoints
+                    .iter()
+                    .rev()
+                    .find_map(|waypt| match waypt {
+                        Waypoint::Snapped(node) => Some(self.router.components[node.0 as usize]),
+                        Waypoint::Free(_) => None,
+                    })
+            })
+            .flatten();
+        let node = match component {
+            Some(component) => self
+                .snap_to_nodes
+                .nearest_neighbor_iter(&pt)
+                .find(|node| self.router.components[node.data.0 as usize] == component)?,
+            None => self.snap_to_nodes.nearest_neighbor(&pt)?,
+        };
+        Some(node.data)
+    }
+
+    fn entire_line_string(&self, route: &Route) -> Option<LineString> {
+        path_to_line_string(&self.router.map, &route.full_path)
     }
 
     // Returns the entire_line_string, but broken into pieces with a snapped=true/false property.
-    fn line_string_broken_down(&self) -> Vec<Feature> {
+    fn line_string_broken_down(&self, route: &Route) -> Vec<Feature> {
         let mut result = Vec::new();
-        if self.route.full_path.is_empty() {
+        if route.full_path.is_empty() {
             return result;
         }
         let mut add_result = |mut pts: Vec<Coord>, snapped: bool| {
@@ -1003,10 +2082,10 @@ impl JsRouteSnapper {
             }
         };
 
-        let mut prev_snapped = !matches!(self.route.full_path[0], PathEntry::FreePoint(_));
+        let mut prev_snapped = !matches!(route.full_path[0], PathEntry::FreePoint(_));
         let mut pts = Vec::new();
 
-        for entry in &self.route.full_path {
+        for entry in &route.full_path {
             let pt = match entry {
                 PathEntry::SnappedPoint(node) => self.router.map.node(*node),
                 PathEntry::FreePoint(pt) => *pt,
@@ -1040,12 +2119,316 @@ impl JsRouteSnapper {
         result
     }
 
-    fn into_polygon_area(&self) -> Option<Geometry> {
-        if !self.route.is_closed_area() {
-            return None;
+    // Uses every closed ring, producing a Polygon or MultiPolygon
+    fn into_polygon_area(&self, route: &Route) -> Option<Geometry> {
+        self.area_geometry(route)
+            .map(|geometry| Geometry::from(&geometry))
+    }
+
+    // Like into_polygon_area, but for geo
+    fn area_geometry(&self, route: &Route) -> Option<geo::Geometry> {
+        let mut polygons = self.area_polygons(route);
+        if polygons.len() > 1 {
+            Some(MultiPolygon(polygons).into())
+        } else {
+            Some(polygons.pop()?.into())
+        }
+    }
+
+    // Everything about a finished route or area, shared by every export format. None if the
+    // route isn't finished.
+    fn route_result(&self, route: &Route) -> Option<export::RouteResult> {
+        let name = self.route_name(route);
+        let (geometry, mut feature) = if self.router.config.area_mode {
+            let geometry = self.area_geometry(route)?;
+            let mut f = Feature::from(Geometry::from(&geometry));
+            self.set_area_properties(&mut f, route);
+            (geometry, f)
+        } else {
+            let linestring = self.entire_line_string(route)?;
+            let length = linestring.length(&Haversine);
+            let mut f = Feature::from(Geometry::from(&linestring));
+            f.set_property("length_meters", length);
+
+            f.set_property(
+                "street_summary",
+                serde_json::to_value(self.street_summary(route)).unwrap(),
+            );
+
+            let mut full_path = Vec::new();
+            for entry in &route.full_path {
+                match entry {
+                    PathEntry::SnappedPoint(node) => {
+                        full_path.push(
+                            serde_json::to_value(&JsonNode {
+                                snapped: Some(node.0),
+                                free: None,
+                            })
+                            .unwrap(),
+                        );
+                    }
+                    PathEntry::FreePoint(pt) => {
+                        full_path.push(
+                            serde_json::to_value(&JsonNode {
+                                snapped: None,
+                                free: Some([trim_lon_lat(pt.x), trim_lon_lat(pt.y)]),
+                            })
+                            .unwrap(),
+                        );
+                    }
+                    PathEntry::Edge(_) => {}
+                }
+            }
+            full_path.dedup();
+            f.set_property("full_path", serde_json::Value::Array(full_path));
+            f.set_property("legs", serde_json::to_value(self.legs(route)).unwrap());
+
+            (linestring.into(), f)
+        };
+
+        // Set these on both LineStrings and Polygons. Areas with more than one ring store the
+        // rest separately, so older callers just see the first.
+        feature.set_property("route_name", name.clone());
+        let mut rings: Vec<&Route> = if self.router.config.area_mode {
+            route
+                .all_rings()
+                .filter(|ring| ring.is_closed_area())
+                .collect()
+        } else {
+            vec![route]
+        };
+        let first = rings.remove(0);
+        feature.set_property("waypoints", self.waypoints_to_json(first));
+        if !rings.is_empty() {
+            feature.set_property(
+                "other_rings",
+                serde_json::Value::Array(
+                    rings
+                        .into_iter()
+                        .map(|ring| self.waypoints_to_json(ring))
+                        .collect(),
+                ),
+            );
+        }
+
+        let waypoints = first
+            .waypoints
+            .iter()
+            .map(|waypt| export::ResultWaypoint {
+                pt: unhash_pt(self.to_pt(*waypt)),
+                name: self.name_waypoint(waypt),
+                snapped: matches!(waypt, Waypoint::Snapped(_)),
+            })
+            .collect();
+        Some(export::RouteResult {
+            name,
+            geometry,
+            waypoints,
+            properties: feature.properties.unwrap_or_default(),
+        })
+    }
+
+    // Describes the path between each pair of waypoints
+    fn legs(&self, route: &Route) -> Vec<JsonLeg> {
+        let indices = route.waypoint_indices();
+        let mut legs = Vec::new();
+        for (idx, pair) in indices.windows(2).enumerate() {
+            let path = &route.full_path[pair[0]..=pair[1]];
+            let length_meters = path_to_line_string(&self.router.map, path)
+                .map(|linestring| linestring.length(&Haversine))
+                .unwrap_or(0.0);
+
+            let mut cost = 0.0;
+            let mut has_edges = false;
+            let mut street_names: Vec<String> = Vec::new();
+            for entry in path {
+                if let PathEntry::Edge(e) = entry {
+                    has_edges = true;
+                    cost += self.router.effective_cost(*e).unwrap_or(0.0);
+                    if let Some(name) = self.edge_name(e.0) {
+                        if street_names.last().map(|x| x.as_str()) != Some(name) {
+                            street_names.push(name.to_string());
+                        }
+                    }
+                }
+            }
+            // If pathfinding failed between two snapped waypoints, there's a straight line
+            let mut routed = false;
+            let mut disconnected = None;
+            if let (Waypoint::Snapped(node1), Waypoint::Snapped(node2)) =
+                (route.waypoints[idx], route.waypoints[idx + 1])
+            {
+                routed = has_edges || node1 == node2;
+                if !routed {
+                    disconnected = Some(self.router.disconnected_reason(node1, node2));
+                }
+            }
+
+            legs.push(JsonLeg {
+                from_waypoint: idx,
+                to_waypoint: idx + 1,
+                length_meters,
+                cost: routed.then_some(cost),
+                routed,
+                disconnected,
+                street_names,
+            });
+        }
+        legs
+    }
+
+    // Names an area from the streets along its boundary, or a route from its endpoints and main
+    // streets
+    fn route_name(&self, route: &Route) -> String {
+        if self.router.config.area_mode {
+            let mut boundary_names = BTreeSet::new();
+            for entry in route.all_rings().flat_map(|ring| &ring.full_path) {
+                if let PathEntry::Edge(e) = entry {
+                    if let Some(name) = self.edge_name(e.0) {
+                        boundary_names.insert(name.to_string());
+                    }
+                }
+            }
+            return self.naming.area_name(boundary_names.into_iter().collect());
+        }
+
+        let from_name = self.name_waypoint(&route.waypoints[0]);
+        let to_name = self.name_waypoint(route.waypoints.last().unwrap());
+        let via = self.via_streets(route, &self.street_summary(route));
+        self.naming.route_name(&from_name, &to_name, via)
+    }
+
+    // Every named street along the route in the order first reached, with the total length of
+    // edges with that name
+    fn street_summary(&self, route: &Route) -> Vec<JsonStreet> {
+        let mut summary: Vec<JsonStreet> = Vec::new();
+        for entry in &route.full_path {
+            if let PathEntry::Edge(e) = entry {
+                let edge = self.router.map.edge(e.0);
+                let Some(name) = self.edge_name(e.0) else {
+                    continue;
+                };
+                match summary.iter_mut().find(|street| street.name == name) {
+                    Some(street) => street.length_meters += edge.length_meters,
+                    None => summary.push(JsonStreet {
+                        name: name.to_string(),
+                        length_meters: edge.length_meters,
+                    }),
+                }
+            }
+        }
+        summary
+    }
+
+    // The longest streets along the route, in route order, to describe it. Streets at either end
+    // are already in the route's name, and short streets aren't worth mentioning.
+    fn via_streets(&self, route: &Route, summary: &[JsonStreet]) -> Vec<String> {
+        let total: f64 = summary.iter().map(|street| street.length_meters).sum();
+        let mut endpoint_names = BTreeSet::new();
+        for waypt in [route.waypoints.first(), route.waypoints.last()]
+            .into_iter()
+            .flatten()
+        {
+            if let Waypoint::Snapped(node) = waypt {
+                for (_, _, e) in self.router.graph.edges(*node) {
+                    if let Some(name) = self.edge_name(e.0) {
+                        endpoint_names.insert(name.to_string());
+                    }
+                }
+            }
+        }
+
+        let mut candidates: Vec<(usize, &JsonStreet)> = summary
+            .iter()
+            .enumerate()
+            .filter(|(_, street)| {
+                !endpoint_names.contains(&street.name)
+                    && street.length_meters >= VIA_MIN_FRACTION * total
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.1.length_meters.total_cmp(&a.1.length_meters));
+        candidates.truncate(MAX_VIA_STREETS);
+        candidates.sort_by_key(|(idx, _)| *idx);
+        candidates
+            .into_iter()
+            .map(|(_, street)| street.name.clone())
+            .collect()
+    }
+
+    // Returns the nodes at both ends of every leg between two snapped waypoints where pathfinding
+    // failed. This is much cheaper than checking `legs`.
+    fn disconnected_legs(&self, route: &Route) -> Vec<(NodeID, NodeID)> {
+        let indices = route.waypoint_indices();
+        let mut legs = Vec::new();
+        for (idx, pair) in indices.windows(2).enumerate() {
+            if let (Waypoint::Snapped(node1), Waypoint::Snapped(node2)) =
+                (route.waypoints[idx], route.waypoints[idx + 1])
+            {
+                if node1 != node2
+                    && !route.full_path[pair[0]..=pair[1]]
+                        .iter()
+                        .any(|entry| matches!(entry, PathEntry::Edge(_)))
+                {
+                    legs.push((node1, node2));
+                }
+            }
+        }
+        legs
+    }
+
+    fn area_polygons(&self, route: &Route) -> Vec<Polygon> {
+        let mut rings = self.closed_rings(route);
+        if self.repair_areas {
+            rings = rings.iter().map(validity::remove_spikes).collect();
+        }
+        let polygons = rings_to_polygons(rings);
+        if self.repair_areas {
+            validity::make_valid(polygons)
+        } else {
+            polygons
+        }
+    }
+
+    // Describes the size of an area, the streets around it, and the part of the graph inside it
+    fn set_area_properties(&self, f: &mut Feature, route: &Route) {
+        let area = MultiPolygon(self.area_polygons(route));
+        f.set_property("area_square_meters", area.geodesic_area_unsigned());
+        let perimeter: f64 = area
+            .iter()
+            .flat_map(|polygon| std::iter::once(polygon.exterior()).chain(polygon.interiors()))
+            .map(|ring| ring.length(&Haversine))
+            .sum();
+        f.set_property("perimeter_meters", perimeter);
+
+        // Anything touching the boundary isn't inside
+        let Some(bbox) = area.bounding_rect() else {
+            return;
+        };
+        let envelope =
+            AABB::from_corners([bbox.min().x, bbox.min().y], [bbox.max().x, bbox.max().y]);
+        let mut nodes: Vec<u32> = self
+            .snap_to_nodes
+            .locate_in_envelope(&envelope)
+            .filter(|obj| area.contains(&Point::from(*obj.geom())))
+            .map(|obj| obj.data.0)
+            .collect();
+        nodes.sort();
+        let mut edges = Vec::new();
+        let mut street_names = BTreeSet::new();
+        for (idx, edge) in self.router.map.edges.iter().enumerate() {
+            if bbox.contains(&edge.geometry) && area.contains(&edge.geometry) {
+                edges.push(idx);
+                if let Some(name) = self.edge_name(EdgeID(idx as u32)) {
+                    street_names.insert(name.to_string());
+                }
+            }
         }
-        let exterior = self.entire_line_string()?;
-        Some(geojson::Geometry::from(&Polygon::new(exterior, Vec::new())))
+        f.set_property("enclosed_node_ids", nodes);
+        f.set_property("enclosed_edge_ids", edges);
+        f.set_property(
+            "enclosed_street_names",
+            street_names.into_iter().collect::<Vec<_>>(),
+        );
     }
 
     fn to_pt(&self, waypt: Waypoint) -> HashedPoint {
@@ -1055,24 +2438,176 @@ impl JsRouteSnapper {
         }
     }
 
+    // The closed area being edited as a counter-clockwise cycle of edges, if it only uses snapped
+    // waypoints
+    fn area_as_face(&self) -> Option<Vec<DirectedEdge>> {
+        if !self.route.is_closed_area()
+            || self
+                .route
+                .waypoints
+                .iter()
+                .any(|waypt| matches!(waypt, Waypoint::Free(_)))
+        {
+            return None;
+        }
+        let mut face: Vec<DirectedEdge> = self
+            .route
+            .full_path
+            .iter()
+            .filter_map(|entry| match entry {
+                PathEntry::Edge(e) => Some(*e),
+                _ => None,
+            })
+            .collect();
+        if faces::face_polygon(&self.router.map, &face).signed_area() < 0.0 {
+            face = face
+                .into_iter()
+                .rev()
+                .map(|e| DirectedEdge(e.0, !e.1))
+                .collect();
+        }
+        Some(face)
+    }
+
+    fn closed_rings(&self, route: &Route) -> Vec<LineString> {
+        route
+            .all_rings()
+            .filter(|ring| ring.is_closed_area())
+            .filter_map(|ring| self.entire_line_string(ring))
+            .collect()
+    }
+
+    // Checks each ring separately, before any repair
+    fn area_problems(&self, route: &Route) -> Vec<(validity::Problem, Vec<Line>)> {
+        self.closed_rings(route)
+            .iter()
+            .flat_map(validity::find_problems)
+            .collect()
+    }
+
+    // Unlike what toFinalFeature produces, this isn't trimmed
+    fn encode_waypoint(&self, waypt: Waypoint) -> RouteWaypoint {
+        let pt = match waypt {
+            Waypoint::Snapped(node) => self.router.map.node(node),
+            Waypoint::Free(pt) => pt,
+        };
+        RouteWaypoint {
+            lon: pt.x,
+            lat: pt.y,
+            snapped: matches!(waypt, Waypoint::Snapped(_)),
+        }
+    }
+
+    fn waypoints_to_json(&self, route: &Route) -> serde_json::Value {
+        let mut waypoints = Vec::new();
+        for waypt in &route.waypoints {
+            let pt = unhash_pt(self.to_pt(*waypt));
+            waypoints.push(
+                serde_json::to_value(&RouteWaypoint {
+                    lon: trim_lon_lat(pt.x),
+                    lat: trim_lon_lat(pt.y),
+                    snapped: matches!(waypt, Waypoint::Snapped(_)),
+                })
+                .unwrap(),
+            );
+        }
+        serde_json::Value::Array(waypoints)
+    }
+
+    // Replaces the active route
+    fn load_rings(&mut self, rings: Vec<Vec<RouteWaypoint>>) -> Result<(), JsValue> {
+        self.reset_active_route();
+        let mut state = Vec::new();
+        for ring in rings {
+            state.push(
+                ring.iter()
+                    .map(|waypt| self.decode_waypoint(waypt))
+                    .collect::<Result<Vec<_>, _>>()?,
+            );
+        }
+        self.route.restore(&self.router, state);
+        Ok(())
+    }
+
+    fn decode_waypoint(&self, waypt: &RouteWaypoint) -> Result<Waypoint, JsValue> {
+        let pt = Coord {
+            x: waypt.lon,
+            y: waypt.lat,
+        };
+        if !waypt.snapped {
+            return Ok(Waypoint::Free(pt));
+        }
+        match self.mouseover_node(pt) {
+            Some(node) => Ok(Waypoint::Snapped(node)),
+            None => Err(JsValue::from_str("A waypoint didn't snap")),
+        }
+    }
+
     fn name_waypoint(&self, waypt: &Waypoint) -> String {
         match waypt {
-            Waypoint::Snapped(node) => {
-                let edge_names = self
-                    .router
-                    .graph
-                    .edges(*node)
-                    .map(|(_, _, edge)| {
-                        self.router.map.edges[edge.0 .0 as usize]
-                            .name
-                            .clone()
-                            .unwrap_or_else(|| "???".to_string())
-                    })
-                    .collect::<BTreeSet<_>>();
-                plain_list_names(edge_names)
+            Waypoint::Snapped(node) => self.name_node(*node),
+            Waypoint::Free(pt) => self.name_free_point(*pt),
+        }
+    }
+
+    // Lists the streets meeting at a node
+    fn name_node(&self, node: NodeID) -> String {
+        let edge_names = self
+            .router
+            .graph
+            .edges(node)
+            .map(|(_, _, edge)| {
+                self.edge_name(edge.0)
+                    .unwrap_or(&self.naming.unnamed_street)
+                    .to_string()
+            })
+            .collect::<BTreeSet<_>>();
+        self.naming.list(edge_names)
+    }
+
+    // Very close to a node, use its streets. Otherwise, describe the nearest named street.
+    fn name_free_point(&self, pt: Coord) -> String {
+        let point = Point::from(pt);
+        if let Some(node) = self.snap_to_nodes.nearest_neighbor(&[pt.x, pt.y]) {
+            let node_pt = Point::from(*node.geom());
+            if Haversine.distance(point, node_pt) <= self.naming.nearby_node_meters {
+                let name = self.name_node(node.data);
+                if name != self.naming.unnamed_street {
+                    return name;
+                }
             }
-            Waypoint::Free(_) => "???".to_string(),
         }
+
+        // Searching in degrees, a segment this far away is definitely too far in meters
+        let max_degrees =
+            self.naming.nearby_street_meters / (METERS_PER_DEGREE * pt.y.to_radians().cos());
+        for (obj, dist_squared) in self
+            .edge_segments
+            .nearest_neighbor_iter_with_distance_2(&[pt.x, pt.y])
+        {
+            if dist_squared.sqrt() > max_degrees {
+                break;
+            }
+            let Some(name) = self.edge_name(obj.data) else {
+                continue;
+            };
+            let line = Line::new(obj.geom().from, obj.geom().to);
+            let closest = match line.closest_point(&point) {
+                Closest::Intersection(x) | Closest::SinglePoint(x) => x,
+                Closest::Indeterminate => continue,
+            };
+            if Haversine.distance(point, closest) <= self.naming.nearby_street_meters {
+                return self.naming.near_street(name);
+            }
+        }
+        self.naming.freehand_point.clone()
+    }
+
+    // The edge's name, in the configured language if possible
+    fn edge_name(&self, id: EdgeID) -> Option<&str> {
+        self.router
+            .map
+            .edge_name(id, self.naming.language.as_deref())
     }
 }
 
@@ -1081,9 +2616,85 @@ impl Route {
         Route {
             waypoints: Vec::new(),
             full_path: Vec::new(),
+            other_rings: Vec::new(),
+        }
+    }
+
+    // The ring being edited, then any others
+    fn all_rings(&self) -> impl Iterator<Item = &Route> {
+        std::iter::once(self).chain(self.other_rings.iter())
+    }
+
+    fn snapshot(&self) -> Vec<Vec<Waypoint>> {
+        self.all_rings()
+            .map(|ring| ring.waypoints.clone())
+            .collect()
+    }
+
+    // Inverse of snapshot
+    fn restore(&mut self, router: &Router, state: Vec<Vec<Waypoint>>) {
+        let mut rings = state.into_iter();
+        self.waypoints = rings.next().unwrap_or_default();
+        self.other_rings = rings
+            .map(|waypoints| {
+                let mut ring = Route::new();
+                ring.waypoints = waypoints;
+                ring
+            })
+            .collect();
+        self.recalculate_all_rings(router);
+    }
+
+    fn recalculate_all_rings(&mut self, router: &Router) {
+        self.recalculate_full_path(router);
+        for ring in &mut self.other_rings {
+            ring.recalculate_full_path(router);
         }
     }
 
+    fn contains(&self, entry: &PathEntry) -> bool {
+        self.all_rings().any(|ring| ring.full_path.contains(entry))
+    }
+
+    // Finishes the ring being edited, leaving an empty one
+    fn start_new_ring(&mut self) {
+        let ring = Route {
+            waypoints: std::mem::take(&mut self.waypoints),
+            full_path: std::mem::take(&mut self.full_path),
+            other_rings: Vec::new(),
+        };
+        self.other_rings.push(ring);
+    }
+
+    // If the ring being edited is finished or empty, start editing the other ring containing this
+    // entry instead. Returns true if this happened.
+    fn focus_ring_containing(&mut self, entry: &PathEntry) -> bool {
+        if self.full_path.contains(entry) || !(self.waypoints.is_empty() || self.is_closed_area()) {
+            return false;
+        }
+        let Some(idx) = self
+            .other_rings
+            .iter()
+            .position(|ring| ring.full_path.contains(entry))
+        else {
+            return false;
+        };
+        let ring = self.other_rings.remove(idx);
+        let old_waypoints = std::mem::replace(&mut self.waypoints, ring.waypoints);
+        let old_full_path = std::mem::replace(&mut self.full_path, ring.full_path);
+        if !old_waypoints.is_empty() {
+            self.other_rings.insert(
+                idx,
+                Route {
+                    waypoints: old_waypoints,
+                    full_path: old_full_path,
+                    other_rings: Vec::new(),
+                },
+            );
+        }
+        true
+    }
+
     fn add_waypoint(&mut self, router: &Router, waypt: Waypoint) {
         if self.waypoints.is_empty() {
             self.waypoints.push(waypt);
@@ -1174,6 +2785,38 @@ impl Route {
         }
     }
 
+    // Returns the index into full_path of every waypoint. If there's only one waypoint, returns
+    // nothing.
+    fn waypoint_indices(&self) -> Vec<usize> {
+        let mut indices = Vec::new();
+        if self.full_path.is_empty() {
+            return indices;
+        }
+        let mut idx = 0;
+        for waypt in &self.waypoints {
+            let entry = waypt.to_path_entry();
+            while self.full_path[idx] != entry {
+                idx += 1;
+            }
+            indices.push(idx);
+        }
+        indices
+    }
+
+    // Makes waypoints[idx] the first and last waypoint of a closed area. Rather than pathfinding
+    // again, which could produce a slightly different polygon, full_path is rotated too.
+    fn rotate_area(&mut self, idx: usize) {
+        let full_idx = self.waypoint_indices()[idx];
+        // The first and last entries are the same
+        let mut full_path = self.full_path[full_idx..].to_vec();
+        full_path.extend(self.full_path[1..=full_idx].iter().cloned());
+        self.full_path = full_path;
+
+        self.waypoints.pop();
+        self.waypoints.rotate_left(idx);
+        self.waypoints.push(self.waypoints[0]);
+    }
+
     fn is_closed_area(&self) -> bool {
         // TODO When area mode is false, somebody could make a linestring like this and mess things
         // up
@@ -1182,6 +2825,14 @@ impl Route {
 }
 
 impl Router {
+    fn add_to_graph(&mut self, id: EdgeID) {
+        let e = self.map.edge(id);
+        self.graph
+            .add_edge(e.node1, e.node2, DirectedEdge(id, FORWARDS));
+        self.graph
+            .add_edge(e.node2, e.node1, DirectedEdge(id, BACKWARDS));
+    }
+
     // Returns a sequence of (SnappedPoint, Edge, SnappedPoint, Edge..., SnappedPoint)
     fn pathfind(
         &self,
@@ -1201,28 +2852,36 @@ impl Router {
             }
         }
 
+        // The contraction hierarchy only knows the original costs
+        if avoid.is_empty() && !self.costs_changed() {
+            if let Some(ref ch) = self.ch {
+                return self.pathfind_ch(ch, node1, node2);
+            }
+        }
+
+        self.astar(node1, node2, |e| if avoid.contains(&e) { 2.0 } else { 1.0 })
+    }
+
+    // Like pathfind, but multiplying each edge's cost by a penalty
+    fn astar<F: Fn(EdgeID) -> f64>(
+        &self,
+        node1: NodeID,
+        node2: NodeID,
+        penalty: F,
+    ) -> Option<Vec<PathEntry>> {
         let node2_pt = self.map.node(node2);
 
-        let (_, path) = petgraph::algo::astar(
+        let (cost, path) = petgraph::algo::astar(
             &self.graph,
             node1,
             |i| i == node2,
-            |(_, _, dir_edge)| {
-                let penalty = if avoid.contains(&dir_edge.0) {
-                    2.0
-                } else {
-                    1.0
-                };
-                let edge = self.map.edge(dir_edge.0);
-                let cost = if dir_edge.1 == FORWARDS {
-                    edge.forward_cost.unwrap()
-                } else {
-                    edge.backward_cost.unwrap()
-                };
-                penalty * cost
-            },
+            |(_, _, dir_edge)| penalty(dir_edge.0) * self.cost(*dir_edge),
             |i| Haversine.distance(Point::from(self.map.node(i)), Point::from(node2_pt)),
         )?;
+        // The only path crosses a blocked edge
+        if cost.is_infinite() {
+            return None;
+        }
 
         let mut entries = Vec::new();
         for pair in path.windows(2) {
@@ -1236,6 +2895,194 @@ impl Router {
         assert!(*entries.last().unwrap() == PathEntry::SnappedPoint(node2));
         Some(entries)
     }
+
+    // Uses the penalty method to find paths between two nodes that differ from the current one:
+    // repeatedly penalize the edges of every candidate, and keep the candidates that aren't too
+    // similar to the others or much more expensive.
+    fn alternatives(
+        &self,
+        node1: NodeID,
+        node2: NodeID,
+        current: &[PathEntry],
+        k: usize,
+    ) -> Vec<Vec<PathEntry>> {
+        let max_cost = ALTERNATIVE_MAX_DETOUR * self.path_cost(current);
+        let mut penalties: HashMap<EdgeID, f64> = HashMap::new();
+        let mut candidate = current.to_vec();
+        let mut found = vec![current.to_vec()];
+
+        for _ in 0..3 * k {
+            for entry in &candidate {
+                if let PathEntry::Edge(dir_edge) = entry {
+                    *penalties.entry(dir_edge.0).or_insert(1.0) *= ALTERNATIVE_PENALTY;
+                }
+            }
+
+            match self.astar(node1, node2, |e| *penalties.get(&e).unwrap_or(&1.0)) {
+                Some(path) => candidate = path,
+                None => break,
+            }
+            if self.path_cost(&candidate) > max_cost {
+                continue;
+            }
+            let length = self.path_length(&candidate);
+            if found.iter().all(|other| {
+                self.shared_length(&candidate, other) < ALTERNATIVE_MAX_OVERLAP * length
+            }) {
+                found.push(candidate.clone());
+                if found.len() > k {
+                    break;
+                }
+            }
+        }
+
+        found.remove(0);
+        found
+    }
+
+    fn path_cost(&self, path: &[PathEntry]) -> f64 {
+        path.iter()
+            .map(|x| match x {
+                PathEntry::Edge(dir_edge) => self.cost(*dir_edge),
+                _ => 0.0,
+            })
+            .sum()
+    }
+
+    fn path_length(&self, path: &[PathEntry]) -> f64 {
+        path.iter()
+            .map(|x| match x {
+                PathEntry::Edge(dir_edge) => self.map.edge(dir_edge.0).length_meters,
+                _ => 0.0,
+            })
+            .sum()
+    }
+
+    // The length of edges in the first path that're also used by the second
+    fn shared_length(&self, path: &[PathEntry], other: &[PathEntry]) -> f64 {
+        let other: HashSet<EdgeID> = other
+            .iter()
+            .filter_map(|x| match x {
+                PathEntry::Edge(dir_edge) => Some(dir_edge.0),
+                _ => None,
+            })
+            .collect();
+        path.iter()
+            .map(|x| match x {
+                PathEntry::Edge(dir_edge) if other.contains(&dir_edge.0) => {
+                    self.map.edge(dir_edge.0).length_meters
+                }
+                _ => 0.0,
+            })
+            .sum()
+    }
+
+    // Explains why there's no path between two nodes. Either they're in different parts of the
+    // graph entirely, or the only connections are one-way the wrong way or can't be used.
+    fn disconnected_reason(&self, node1: NodeID, node2: NodeID) -> &'static str {
+        if self.weak_components[node1.0 as usize] == self.weak_components[node2.0 as usize] {
+            "restricted"
+        } else {
+            "different_component"
+        }
+    }
+
+    // Labels nodes by component. Must be called after changing costs or the graph.
+    fn update_components(&mut self) {
+        let components = self.map.strongly_connected_components_with(|e, dir| {
+            self.effective_cost(DirectedEdge(e, dir)).is_some()
+        });
+        self.components = components;
+        self.weak_components = self.map.strongly_connected_components_with(|_, _| true);
+    }
+
+    // Multiplies the cost of an edge by every zone it intersects
+    fn apply_zones(&mut self, edge: EdgeID) {
+        self.zone_multipliers.remove(&edge);
+        let geometry = &self.map.edge(edge).geometry;
+        for (polygons, multiplier) in &self.zones {
+            if polygons.intersects(geometry) {
+                *self.zone_multipliers.entry(edge).or_insert(1.0) *= multiplier;
+            }
+        }
+    }
+
+    // The cost of crossing an edge, including overrides and zones. None if the edge can't be used.
+    fn effective_cost(&self, dir_edge: DirectedEdge) -> Option<f64> {
+        let cost = match self.cost_overrides.get(&(dir_edge.0, dir_edge.1)) {
+            Some(cost) => *cost,
+            None => {
+                let edge = self.map.edge(dir_edge.0);
+                if dir_edge.1 == FORWARDS {
+                    edge.forward_cost
+                } else {
+                    edge.backward_cost
+                }
+            }
+        }?;
+        let cost = cost * self.zone_multipliers.get(&dir_edge.0).unwrap_or(&1.0);
+        cost.is_finite().then_some(cost)
+    }
+
+    // Like effective_cost, but infinite if the edge can't be used
+    fn cost(&self, dir_edge: DirectedEdge) -> f64 {
+        self.effective_cost(dir_edge).unwrap_or(f64::INFINITY)
+    }
+
+    // True if costs differ from the ones in the map
+    fn costs_changed(&self) -> bool {
+        !self.zone_multipliers.is_empty() || !self.cost_overrides.is_empty()
+    }
+
+    fn pathfind_ch(
+        &self,
+        ch: &ContractionHierarchy,
+        node1: NodeID,
+        node2: NodeID,
+    ) -> Option<Vec<PathEntry>> {
+        let mut entries = vec![PathEntry::SnappedPoint(node1)];
+        for (id, forwards) in ch.pathfind(node1, node2)? {
+            let edge = self.map.edge(id);
+            let (dir, next) = if forwards {
+                (FORWARDS, edge.node2)
+            } else {
+                (BACKWARDS, edge.node1)
+            };
+            entries.push(PathEntry::Edge(DirectedEdge(id, dir)));
+            entries.push(PathEntry::SnappedPoint(next));
+        }
+        assert!(*entries.last().unwrap() == PathEntry::SnappedPoint(node2));
+        Some(entries)
+    }
+}
+
+fn path_to_line_string(map: &RouteSnapperMap, path: &[PathEntry]) -> Option<LineString> {
+    if path.is_empty() {
+        return None;
+    }
+    let mut pts = Vec::new();
+
+    for entry in path {
+        match entry {
+            PathEntry::SnappedPoint(node) => {
+                // There may be an adjacent Edge that contributes geometry, but maybe not near
+                // free points. We'll dedupe later anyway.
+                pts.push(map.node(*node));
+            }
+            PathEntry::FreePoint(pt) => {
+                pts.push(*pt);
+            }
+            PathEntry::Edge(dir_edge) => {
+                pts.extend(edge_geometry(map, *dir_edge));
+            }
+        }
+    }
+
+    pts.dedup();
+    if pts.len() < 2 {
+        return None;
+    }
+    Some(LineString::new(pts))
 }
 
 fn edge_geometry(map: &RouteSnapperMap, dir_edge: DirectedEdge) -> Vec<Coord> {
@@ -1246,6 +3093,55 @@ fn edge_geometry(map: &RouteSnapperMap, dir_edge: DirectedEdge) -> Vec<Coord> {
     pts
 }
 
+// A ring inside an odd number of other rings becomes a hole in the smallest of them. Everything
+// else is an exterior.
+fn rings_to_polygons(rings: Vec<LineString>) -> Vec<Polygon> {
+    let simple: Vec<Polygon> = rings
+        .into_iter()
+        .map(|ring| Polygon::new(ring, Vec::new()))
+        .collect();
+    let containers: Vec<Vec<usize>> = simple
+        .iter()
+        .enumerate()
+        .map(|(idx1, inner)| {
+            simple
+                .iter()
+                .enumerate()
+                .filter(|(idx2, outer)| idx1 != *idx2 && outer.contains(inner))
+                .map(|(idx2, _)| idx2)
+                .collect()
+        })
+        .collect();
+    let is_hole = |idx: usize| containers[idx].len() % 2 == 1;
+
+    let mut polygons: BTreeMap<usize, Polygon> = BTreeMap::new();
+    for (idx, polygon) in simple.iter().enumerate() {
+        if !is_hole(idx) {
+            polygons.insert(idx, polygon.clone());
+        }
+    }
+    for (idx, polygon) in simple.iter().enumerate() {
+        if !is_hole(idx) {
+            continue;
+        }
+        let parent = containers[idx]
+            .iter()
+            .filter(|x| !is_hole(**x))
+            .min_by(|a, b| {
+                simple[**a]
+                    .unsigned_area()
+                    .total_cmp(&simple[**b].unsigned_area())
+            });
+        if let Some(parent) = parent {
+            polygons
+                .get_mut(parent)
+                .unwrap()
+                .interiors_push(polygon.exterior().clone());
+        }
+    }
+    polygons.into_values().collect()
+}
+
 fn err_to_js<E: std::fmt::Display>(err: E) -> JsValue {
     JsValue::from_str(&err.to_string())
 }
@@ -1258,6 +3154,34 @@ struct RouteWaypoint {
     snapped: bool,
 }
 
+// The editing state, for exportSession and importSession
+#[derive(Serialize, Deserialize)]
+struct Session {
+    version: usize,
+    config: Config,
+    // Config doesn't deserialize this
+    area_mode: bool,
+    snap_mode: bool,
+    repair_areas: bool,
+    refuse_disconnected: bool,
+    snap_within_component: bool,
+    naming: Naming,
+    active_route: u32,
+    routes: Vec<SessionRoute>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SessionRoute {
+    id: u32,
+    // The waypoints of every ring. The first is the one being edited, and there's only one unless
+    // the route is an area with holes or multiple parts.
+    rings: Vec<Vec<RouteWaypoint>>,
+    // Oldest first
+    undo: Vec<Vec<Vec<RouteWaypoint>>>,
+    // The last is the next one to redo
+    redo: Vec<Vec<Vec<RouteWaypoint>>>,
+}
+
 // TODO A variation of RouteWaypoint that's easier to make work with MapLibre markers
 #[derive(Deserialize)]
 struct NewRouteWaypoint {
@@ -1265,32 +3189,13 @@ struct NewRouteWaypoint {
     snapped: bool,
 }
 
+// Indexes every segment of every edge
 // Per https://datatracker.ietf.org/doc/html/rfc7946#section-11.2, 6 decimal places (10cm) is
 // plenty of precision
 fn trim_lon_lat(x: f64) -> f64 {
     (x * 10e6).round() / 10e6
 }
 
-fn plain_list_names(names: BTreeSet<String>) -> String {
-    let mut s = String::new();
-    let len = names.len();
-    for (idx, n) in names.into_iter().enumerate() {
-        if idx != 0 {
-            if idx == len - 1 {
-                if len == 2 {
-                    write!(s, " and ").unwrap();
-                } else {
-                    write!(s, ", and ").unwrap();
-                }
-            } else {
-                write!(s, ", ").unwrap();
-            }
-        }
-        write!(s, "{}", n).unwrap();
-    }
-    s
-}
-
 // TODO Hack, make render_geojson do something simpler
 #[derive(Clone, Copy, PartialOrd, Ord, PartialEq, Eq)]
 struct HashedPoint(i64, i64);
@@ -1304,6 +3209,30 @@ fn unhash_pt(pt: HashedPoint) -> Coord {
     }
 }
 
+// One leg of a route, between two waypoints
+#[derive(Serialize)]
+struct JsonLeg {
+    from_waypoint: usize,
+    to_waypoint: usize,
+    length_meters: f64,
+    // The sum of edge costs, or None for straight lines
+    cost: Option<f64>,
+    // False for freehand lines, or when pathfinding failed
+    routed: bool,
+    // When pathfinding failed between snapped waypoints, why
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disconnected: Option<&'static str>,
+    // The names of edges along the leg in order, without unnamed edges
+    street_names: Vec<String>,
+}
+
+// A street along a route, and how much of the route uses it
+#[derive(Serialize)]
+struct JsonStreet {
+    name: String,
+    length_meters: f64,
+}
+
 #[derive(Serialize)]
 struct JsonNode {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -0,0 +1,748 @@
+//! Map-matches a GPS trace onto the graph, using a hidden Markov model as described in Newson and
+//! Krumm's "Hidden Markov Map Matching Through Noise and Sparseness". Each trace point could be on
+//! any nearby edge. Candidates close to the point are more likely, and so are transitions between
+//! candidates where the distance along the network is close to the straight-line distance. The
+//! most likely sequence is then reduced to the fewest waypoints that reproduce it.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use geo::{Closest, ClosestPoint, Coord, Distance, Haversine, LineString, Point, Simplify};
+use route_snapper_graph::{EdgeID, NodeID};
+use rstar::primitives::GeomWithData;
+use rstar::RTree;
+use serde::Serialize;
+
+use crate::{
+    gpx, DirectedEdge, PathEntry, Router, Waypoint, BACKWARDS, FORWARDS, METERS_PER_DEGREE,
+};
+
+// Consider edges within this many multiples of the GPS accuracy from each trace point
+const SEARCH_RADIUS_FACTOR: f64 = 4.0;
+// Consider at most this many edges per trace point
+const MAX_CANDIDATES: usize = 5;
+// How quickly a transition becomes unlikely as the distance along the network differs from the
+// straight-line distance
+const TRANSITION_SCALE_METERS: f64 = 10.0;
+// Don't search for paths between candidates much longer than the straight line between trace
+// points
+const MAX_DETOUR_FACTOR: f64 = 3.0;
+
+/// How well a trace was matched
+#[derive(Serialize)]
+pub struct MatchQuality {
+    /// The fraction of trace points matched to the network. The rest become freehand waypoints.
+    pub matched_fraction: f64,
+    /// The mean distance from each trace point to the resulting route. None if the route has no
+    /// line.
+    pub mean_distance_meters: Option<f64>,
+    /// The largest distance from any trace point to the resulting route. None if the route has no
+    /// line.
+    pub max_distance_meters: Option<f64>,
+    pub trace_length_meters: f64,
+    pub route_length_meters: f64,
+    pub num_waypoints: usize,
+}
+
+pub struct MatchResult {
+    pub waypoints: Vec<Waypoint>,
+    /// The fraction of trace points matched to the network, after skipping points very close
+    /// together
+    pub matched_fraction: f64,
+}
+
+// A place on an edge where a trace point might really be
+struct Candidate {
+    edge: EdgeID,
+    // Meters from the trace point
+    distance: f64,
+    // Meters along the edge from node1
+    offset: f64,
+}
+
+// How to get from one candidate to the next. The edges include the partly crossed edges of both
+// candidates, unless the path stays on one edge.
+struct Transition {
+    distance: f64,
+    edges: Vec<DirectedEdge>,
+}
+
+// One trace point in the Viterbi algorithm. Per candidate, the log-probability of the most likely
+// sequence ending there, and the previous candidate in that sequence, if any.
+struct Step {
+    scores: Vec<f64>,
+    previous: Vec<Option<(usize, Transition)>>,
+}
+
+/// Matches a trace onto the graph. Trace points more than a few multiples of `gps_accuracy_meters`
+/// from any usable edge become freehand waypoints.
+pub fn match_trace(
+    router: &Router,
+    edge_segments: &RTree<GeomWithData<rstar::primitives::Line<[f64; 2]>, EdgeID>>,
+    trace: &[Coord],
+    gps_accuracy_meters: f64,
+) -> MatchResult {
+    let search_radius = SEARCH_RADIUS_FACTOR * gps_accuracy_meters;
+    let observations = downsample(trace, gps_accuracy_meters);
+    let candidates: Vec<Vec<Candidate>> = observations
+        .iter()
+        .map(|pt| find_candidates(router, edge_segments, *pt, search_radius))
+        .collect();
+    let matched = candidates.iter().filter(|x| !x.is_empty()).count();
+    let matched_fraction = if observations.is_empty() {
+        0.0
+    } else {
+        matched as f64 / observations.len() as f64
+    };
+
+    let emission = |c: &Candidate| -0.5 * (c.distance / gps_accuracy_meters).powi(2);
+    let mut steps: Vec<Step> = Vec::new();
+    for (idx, here) in candidates.iter().enumerate() {
+        let mut scores = vec![f64::NEG_INFINITY; here.len()];
+        let mut previous: Vec<Option<(usize, Transition)>> = here.iter().map(|_| None).collect();
+        if let Some(prev_step) = steps.last() {
+            if !here.is_empty() && !prev_step.scores.is_empty() {
+                let straight_line = Haversine.distance(
+                    Point::from(observations[idx - 1]),
+                    Point::from(observations[idx]),
+                );
+                let mut transitions = transitions(
+                    router,
+                    &candidates[idx - 1],
+                    here,
+                    MAX_DETOUR_FACTOR * straight_line + 2.0 * search_radius,
+                    gps_accuracy_meters,
+                );
+                for (to, candidate) in here.iter().enumerate() {
+                    let mut best = None;
+                    for (from, prev_score) in prev_step.scores.iter().enumerate() {
+                        let Some(ref transition) = transitions[from][to] else {
+                            continue;
+                        };
+                        let score = prev_score
+                            - (transition.distance - straight_line).abs() / TRANSITION_SCALE_METERS
+                            + emission(candidate);
+                        if score > scores[to] {
+                            scores[to] = score;
+                            best = Some(from);
+                        }
+                    }
+                    if let Some(from) = best {
+                        previous[to] = Some((from, transitions[from][to].take().unwrap()));
+                    }
+                }
+            }
+        }
+        // If nothing is reachable from the previous trace point, start a new sequence here
+        if previous.iter().all(|x| x.is_none()) {
+            scores = here.iter().map(emission).collect();
+        }
+        steps.push(Step { scores, previous });
+    }
+
+    // Working backwards, find the most likely sequence ending at each trace point that doesn't
+    // continue to the next
+    let mut chains = Vec::new();
+    let mut end = steps.len();
+    while end > 0 {
+        let mut idx = end - 1;
+        let Some(mut candidate) = argmax(&steps[idx].scores) else {
+            end -= 1;
+            continue;
+        };
+        let mut chain = Vec::new();
+        loop {
+            match steps[idx].previous[candidate].take() {
+                Some((from, transition)) => {
+                    chain.push((idx, candidate, Some(transition)));
+                    idx -= 1;
+                    candidate = from;
+                }
+                None => {
+                    chain.push((idx, candidate, None));
+                    break;
+                }
+            }
+        }
+        chain.reverse();
+        chains.push(chain);
+        end = idx;
+    }
+    chains.reverse();
+
+    // Reproduce each sequence with snapped waypoints. Anything else is off the network.
+    let mut builder = WaypointBuilder {
+        router,
+        waypoints: Vec::new(),
+        full_path: Vec::new(),
+    };
+    let mut freehand = Vec::new();
+    let mut next_idx = 0;
+    for chain in chains {
+        let start = chain[0].0;
+        let end = chain.last().unwrap().0 + 1;
+        freehand.extend_from_slice(&observations[next_idx..start]);
+        match chain_edges(router, &candidates, chain) {
+            Some(edges) => {
+                builder.add_free_points(std::mem::take(&mut freehand), gps_accuracy_meters);
+                builder.add_edges(&edges);
+            }
+            // The sequence didn't really leave one spot, so treat it like it's off the network
+            None => freehand.extend_from_slice(&observations[start..end]),
+        }
+        next_idx = end;
+    }
+    freehand.extend_from_slice(&observations[next_idx..]);
+    builder.add_free_points(freehand, gps_accuracy_meters);
+
+    MatchResult {
+        waypoints: builder.waypoints,
+        matched_fraction,
+    }
+}
+
+/// Reads a trace from a GPX file's track, or from a GeoJSON LineString, either by itself or as the
+/// first feature.
+pub fn read_trace(input: &str) -> Result<Vec<Coord>, String> {
+    let trace: Vec<Coord> = if input.trim_start().starts_with('<') {
+        gpx::read_track_points(input)?
+            .into_iter()
+            .map(|point| point.pt)
+            .collect()
+    } else {
+        let gj: geojson::GeoJson = input.parse().map_err(|err| format!("{err}"))?;
+        let geometry = match gj {
+            geojson::GeoJson::Geometry(geometry) => Some(geometry),
+            geojson::GeoJson::Feature(f) => f.geometry,
+            geojson::GeoJson::FeatureCollection(fc) => {
+                fc.features.into_iter().next().and_then(|f| f.geometry)
+            }
+        };
+        let Some(geojson::Value::LineString(coords)) = geometry.map(|g| g.value) else {
+            return Err("A trace must be a LineString".to_string());
+        };
+        coords
+            .into_iter()
+            .map(|pt| Coord { x: pt[0], y: pt[1] })
+            .collect()
+    };
+
+    // Points off the globe, including NaN, can't be looked up in the RTree
+    if let Some(pt) = trace
+        .iter()
+        .find(|pt| !(pt.x.abs() <= 180.0 && pt.y.abs() <= 90.0))
+    {
+        return Err(format!("Bad trace point {}, {}", pt.x, pt.y));
+    }
+    Ok(trace)
+}
+
+/// Measures how closely the matched route follows the original trace. The route may not have a
+/// line, if matching failed.
+pub fn quality(
+    trace: &[Coord],
+    route: Option<&LineString>,
+    matched_fraction: f64,
+    num_waypoints: usize,
+) -> MatchQuality {
+    let distances: Option<Vec<f64>> = route
+        .filter(|route| route.0.len() >= 2 && !trace.is_empty())
+        .map(|route| {
+            trace
+                .iter()
+                .filter_map(|pt| {
+                    let point = Point::from(*pt);
+                    match route.closest_point(&point) {
+                        Closest::Intersection(x) | Closest::SinglePoint(x) => {
+                            Some(Haversine.distance(point, x))
+                        }
+                        Closest::Indeterminate => None,
+                    }
+                })
+                .collect()
+        });
+    MatchQuality {
+        matched_fraction,
+        mean_distance_meters: distances
+            .as_ref()
+            .map(|x| x.iter().sum::<f64>() / x.len() as f64),
+        max_distance_meters: distances.map(|x| x.into_iter().fold(0.0, f64::max)),
+        trace_length_meters: line_length(trace),
+        route_length_meters: route.map(|route| line_length(&route.0)).unwrap_or(0.0),
+        num_waypoints,
+    }
+}
+
+// Skips trace points closer than the GPS accuracy to the previous one, always keeping the last.
+// These don't add information, but make matching slower.
+fn downsample(trace: &[Coord], gps_accuracy_meters: f64) -> Vec<Coord> {
+    let mut result: Vec<Coord> = Vec::new();
+    for (idx, pt) in trace.iter().enumerate() {
+        let keep = match result.last() {
+            Some(last) => {
+                (idx == trace.len() - 1 && last != pt)
+                    || Haversine.distance(Point::from(*last), Point::from(*pt))
+                        >= gps_accuracy_meters
+            }
+            None => true,
+        };
+        if keep {
+            result.push(*pt);
+        }
+    }
+    result
+}
+
+// Finds the closest place on each nearby usable edge
+fn find_candidates(
+    router: &Router,
+    edge_segments: &RTree<GeomWithData<rstar::primitives::Line<[f64; 2]>, EdgeID>>,
+    pt: Coord,
+    search_radius: f64,
+) -> Vec<Candidate> {
+    // Searching in degrees, a segment this far away is definitely too far in meters
+    let max_degrees = search_radius / (METERS_PER_DEGREE * pt.y.to_radians().cos());
+    let mut seen = HashSet::new();
+    let mut candidates = Vec::new();
+    for (obj, dist_squared) in edge_segments.nearest_neighbor_iter_with_distance_2(&[pt.x, pt.y]) {
+        if dist_squared.sqrt() > max_degrees || candidates.len() == MAX_CANDIDATES {
+            break;
+        }
+        if !seen.insert(obj.data) {
+            continue;
+        }
+        if router
+            .effective_cost(DirectedEdge(obj.data, FORWARDS))
+            .is_none()
+            && router
+                .effective_cost(DirectedEdge(obj.data, BACKWARDS))
+                .is_none()
+        {
+            continue;
+        }
+        if let Some(candidate) = project(router, obj.data, pt) {
+            if candidate.distance <= search_radius {
+                candidates.push(candidate);
+            }
+        }
+    }
+    candidates
+}
+
+// Finds the closest point on the edge to pt
+fn project(router: &Router, id: EdgeID, pt: Coord) -> Option<Candidate> {
+    let edge = router.map.edge(id);
+    let point = Point::from(pt);
+    let mut best: Option<Candidate> = None;
+    let mut length_before = 0.0;
+    for line in edge.geometry.lines() {
+        let closest = match line.closest_point(&point) {
+            Closest::Intersection(x) | Closest::SinglePoint(x) => x,
+            Closest::Indeterminate => continue,
+        };
+        let distance = Haversine.distance(point, closest);
+        if best.as_ref().map(|x| distance < x.distance).unwrap_or(true) {
+            best = Some(Candidate {
+                edge: id,
+                distance,
+                offset: (length_before + Haversine.distance(Point::from(line.start), closest))
+                    .min(edge.length_meters),
+            });
+        }
+        length_before += line_length(&[line.start, line.end]);
+    }
+    best
+}
+
+// Finds the shortest way between every pair of candidates, or None if there isn't one within
+// max_distance. Small movements backwards along one-way edges are allowed, because of noise.
+fn transitions(
+    router: &Router,
+    from: &[Candidate],
+    to: &[Candidate],
+    max_distance: f64,
+    gps_accuracy_meters: f64,
+) -> Vec<Vec<Option<Transition>>> {
+    let mut searches: HashMap<NodeID, HashMap<NodeID, (f64, Option<DirectedEdge>)>> =
+        HashMap::new();
+    let mut result = Vec::new();
+    for a in from {
+        let mut row = Vec::new();
+        for b in to {
+            let mut best: Option<Transition> = None;
+            if a.edge == b.edge {
+                let change = b.offset - a.offset;
+                if change.abs() <= gps_accuracy_meters
+                    || router
+                        .effective_cost(DirectedEdge(a.edge, change > 0.0))
+                        .is_some()
+                {
+                    best = Some(Transition {
+                        distance: change.abs(),
+                        edges: Vec::new(),
+                    });
+                }
+            }
+
+            for (exit_edge, exit_node, exit_distance) in exits(router, a) {
+                let search = searches
+                    .entry(exit_node)
+                    .or_insert_with(|| search(router, exit_node, max_distance));
+                for (entry_edge, entry_node, entry_distance) in entrances(router, b) {
+                    let Some((distance, _)) = search.get(&entry_node) else {
+                        continue;
+                    };
+                    let distance = exit_distance + distance + entry_distance;
+                    if best.as_ref().map(|x| distance < x.distance).unwrap_or(true) {
+                        let mut edges = vec![exit_edge];
+                        edges.extend(path_to(router, search, entry_node));
+                        edges.push(entry_edge);
+                        best = Some(Transition { distance, edges });
+                    }
+                }
+            }
+            row.push(best);
+        }
+        result.push(row);
+    }
+    result
+}
+
+// The ways to leave a candidate's edge: the edge crossed, the node reached, and the distance to it
+fn exits(router: &Router, c: &Candidate) -> Vec<(DirectedEdge, NodeID, f64)> {
+    let edge = router.map.edge(c.edge);
+    let mut result = Vec::new();
+    if router
+        .effective_cost(DirectedEdge(c.edge, FORWARDS))
+        .is_some()
+    {
+        result.push((
+            DirectedEdge(c.edge, FORWARDS),
+            edge.node2,
+            edge.length_meters - c.offset,
+        ));
+    }
+    if router
+        .effective_cost(DirectedEdge(c.edge, BACKWARDS))
+        .is_some()
+    {
+        result.push((DirectedEdge(c.edge, BACKWARDS), edge.node1, c.offset));
+    }
+    result
+}
+
+// The ways to reach a candidate: the edge crossed, the node it starts from, and the distance
+// from there
+fn entrances(router: &Router, c: &Candidate) -> Vec<(DirectedEdge, NodeID, f64)> {
+    let edge = router.map.edge(c.edge);
+    let mut result = Vec::new();
+    if router
+        .effective_cost(DirectedEdge(c.edge, FORWARDS))
+        .is_some()
+    {
+        result.push((DirectedEdge(c.edge, FORWARDS), edge.node1, c.offset));
+    }
+    if router
+        .effective_cost(DirectedEdge(c.edge, BACKWARDS))
+        .is_some()
+    {
+        result.push((
+            DirectedEdge(c.edge, BACKWARDS),
+            edge.node2,
+            edge.length_meters - c.offset,
+        ));
+    }
+    result
+}
+
+// Dijkstra's algorithm by length from one node, stopping at max_distance. Returns the distance to
+// every node reached and the edge used to get there.
+fn search(
+    router: &Router,
+    start: NodeID,
+    max_distance: f64,
+) -> HashMap<NodeID, (f64, Option<DirectedEdge>)> {
+    let mut reached = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    reached.insert(start, (0.0, None));
+    queue.push(Item {
+        distance: 0.0,
+        node: start,
+    });
+    while let Some(Item { distance, node }) = queue.pop() {
+        if distance > max_distance {
+            break;
+        }
+        if distance > reached[&node].0 {
+            continue;
+        }
+        for (_, next, dir_edge) in router.graph.edges(node) {
+            if router.effective_cost(*dir_edge).is_none() {
+                continue;
+            }
+            let next_distance = distance + router.map.edge(dir_edge.0).length_meters;
+            if reached
+                .get(&next)
+                .map(|(x, _)| next_distance < *x)
+                .unwrap_or(true)
+            {
+                reached.insert(next, (next_distance, Some(*dir_edge)));
+                queue.push(Item {
+                    distance: next_distance,
+                    node: next,
+                });
+            }
+        }
+    }
+    reached
+}
+
+// Follows the edges found by search back to the start
+fn path_to(
+    router: &Router,
+    search: &HashMap<NodeID, (f64, Option<DirectedEdge>)>,
+    mut node: NodeID,
+) -> Vec<DirectedEdge> {
+    let mut path = Vec::new();
+    while let Some(dir_edge) = search[&node].1 {
+        path.push(dir_edge);
+        node = from_node(router, dir_edge);
+    }
+    path.reverse();
+    path
+}
+
+// Joins up the transitions of one sequence of candidates. Returns None if the sequence doesn't
+// meaningfully follow any edge.
+fn chain_edges(
+    router: &Router,
+    candidates: &[Vec<Candidate>],
+    chain: Vec<(usize, usize, Option<Transition>)>,
+) -> Option<Vec<DirectedEdge>> {
+    let first = &candidates[chain[0].0][chain[0].1];
+    let last = &candidates[chain[chain.len() - 1].0][chain[chain.len() - 1].1];
+
+    let mut edges: Vec<DirectedEdge> = Vec::new();
+    for (_, _, transition) in chain {
+        for dir_edge in transition.into_iter().flat_map(|x| x.edges) {
+            match edges.last() {
+                // Still on the same edge
+                Some(prev) if *prev == dir_edge => {}
+                // Turning around partway along an edge
+                Some(prev) if prev.0 == dir_edge.0 => {
+                    edges.pop();
+                }
+                _ => edges.push(dir_edge),
+            }
+        }
+    }
+
+    if edges.is_empty() {
+        // Only use the edge if the trace covered most of it
+        let length = router.map.edge(first.edge).length_meters;
+        let change = last.offset - first.offset;
+        if first.edge == last.edge && change.abs() > length / 2.0 {
+            return Some(vec![DirectedEdge(first.edge, change > 0.0)]);
+        }
+        return None;
+    }
+
+    // The first and last edges are only partly crossed. Skip them if the trace covered less than
+    // half.
+    if edges.len() > 1 && edges[0].0 == first.edge {
+        let length = router.map.edge(first.edge).length_meters;
+        let covered = if edges[0].1 == FORWARDS {
+            length - first.offset
+        } else {
+            first.offset
+        };
+        if covered < length / 2.0 {
+            edges.remove(0);
+        }
+    }
+    if edges.len() > 1 && edges[edges.len() - 1].0 == last.edge {
+        let length = router.map.edge(last.edge).length_meters;
+        let covered = if edges[edges.len() - 1].1 == FORWARDS {
+            last.offset
+        } else {
+            length - last.offset
+        };
+        if covered < length / 2.0 {
+            edges.pop();
+        }
+    }
+    Some(edges)
+}
+
+// Builds up waypoints like the user would, tracking the full path the same way Route does
+struct WaypointBuilder<'a> {
+    router: &'a Router,
+    waypoints: Vec<Waypoint>,
+    full_path: Vec<PathEntry>,
+}
+
+impl WaypointBuilder<'_> {
+    // Adds freehand waypoints, simplifying the line between them first
+    fn add_free_points(&mut self, pts: Vec<Coord>, gps_accuracy_meters: f64) {
+        if pts.is_empty() {
+            return;
+        }
+        let line = LineString::from(pts).simplify(gps_accuracy_meters / METERS_PER_DEGREE);
+        for pt in line.0 {
+            self.waypoints.push(Waypoint::Free(pt));
+            self.full_path.push(PathEntry::FreePoint(pt));
+        }
+    }
+
+    // Adds snapped waypoints to make the route follow these connected edges. From each waypoint,
+    // the next is placed as far along as possible while the route between them still follows the
+    // edges.
+    fn add_edges(&mut self, edges: &[DirectedEdge]) {
+        let mut nodes = vec![from_node(self.router, edges[0])];
+        nodes.extend(edges.iter().map(|e| to_node(self.router, *e)));
+
+        if self.waypoints.last() != Some(&Waypoint::Snapped(nodes[0])) {
+            self.waypoints.push(Waypoint::Snapped(nodes[0]));
+            self.full_path.push(PathEntry::SnappedPoint(nodes[0]));
+        }
+        let mut idx = 0;
+        while idx < edges.len() {
+            let (next, entries) = match self.furthest_followed(&nodes, edges, idx) {
+                Some((next, entries)) => (next, Some(entries)),
+                // The route can't follow the very next edge, so just add a waypoint at its end
+                None => (
+                    idx + 1,
+                    self.router
+                        .pathfind(nodes[idx], nodes[idx + 1], &self.full_path),
+                ),
+            };
+
+            self.waypoints.push(Waypoint::Snapped(nodes[next]));
+            match entries {
+                Some(entries) => {
+                    self.full_path.pop();
+                    self.full_path.extend(entries);
+                }
+                None => self.full_path.push(PathEntry::SnappedPoint(nodes[next])),
+            }
+            idx = next;
+        }
+    }
+
+    // Finds the furthest node after `nodes[idx]` that the route can reach while following the
+    // edges, and the path there. Checking every node would take a pathfind each, so this takes
+    // exponentially bigger steps until the route stops following the edges, then binary searches.
+    // That assumes routes to nearer nodes follow the edges too, which is usually true.
+    fn furthest_followed(
+        &self,
+        nodes: &[NodeID],
+        edges: &[DirectedEdge],
+        idx: usize,
+    ) -> Option<(usize, Vec<PathEntry>)> {
+        let reach = |next: usize| {
+            self.router
+                .pathfind(nodes[idx], nodes[next], &self.full_path)
+                .filter(|entries| follows(entries, &edges[idx..next]))
+        };
+
+        let mut best = None;
+        let mut step = 1;
+        let mut failed = None;
+        while failed.is_none() {
+            let next = (idx + step).min(edges.len());
+            match reach(next) {
+                Some(entries) => {
+                    best = Some((next, entries));
+                    if next == edges.len() {
+                        break;
+                    }
+                    step *= 2;
+                }
+                None => failed = Some(next),
+            }
+        }
+
+        if let Some(mut bad) = failed {
+            let mut good = best.as_ref().map(|(next, _)| *next).unwrap_or(idx);
+            while bad - good > 1 {
+                let middle = (good + bad) / 2;
+                match reach(middle) {
+                    Some(entries) => {
+                        best = Some((middle, entries));
+                        good = middle;
+                    }
+                    None => bad = middle,
+                }
+            }
+        }
+        best
+    }
+}
+
+// True if a path crosses exactly these edges
+fn follows(entries: &[PathEntry], edges: &[DirectedEdge]) -> bool {
+    entries
+        .iter()
+        .filter_map(|entry| match entry {
+            PathEntry::Edge(dir_edge) => Some(*dir_edge),
+            _ => None,
+        })
+        .eq(edges.iter().cloned())
+}
+
+fn from_node(router: &Router, dir_edge: DirectedEdge) -> NodeID {
+    let edge = router.map.edge(dir_edge.0);
+    if dir_edge.1 == FORWARDS {
+        edge.node1
+    } else {
+        edge.node2
+    }
+}
+
+fn to_node(router: &Router, dir_edge: DirectedEdge) -> NodeID {
+    let edge = router.map.edge(dir_edge.0);
+    if dir_edge.1 == FORWARDS {
+        edge.node2
+    } else {
+        edge.node1
+    }
+}
+
+fn argmax(scores: &[f64]) -> Option<usize> {
+    scores
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| x.is_finite())
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(idx, _)| idx)
+}
+
+fn line_length(pts: &[Coord]) -> f64 {
+    pts.windows(2)
+        .map(|pair| Haversine.distance(Point::from(pair[0]), Point::from(pair[1])))
+        .sum()
+}
+
+// Orders by shortest distance first, for use in a BinaryHeap
+#[derive(PartialEq)]
+struct Item {
+    distance: f64,
+    node: NodeID,
+}
+
+impl Eq for Item {}
+
+impl PartialOrd for Item {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Item {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .distance
+            .total_cmp(&self.distance)
+            .then_with(|| self.node.cmp(&other.node))
+    }
+}
@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+
+/// Text used to name routes, areas, and waypoints, so it can be translated. Templates contain
+/// placeholders in braces, like `{from}`.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Naming {
+    /// Prefer edge names in this language, like `cy` for names from `name:cy` tags. Edges without
+    /// a name in this language use their default name.
+    pub language: Option<String>,
+    /// Names a route from its first and last waypoint, `{from}` and `{to}`
+    pub route: String,
+    /// Like `route`, but also with `{via}`, a list of the main streets along the route
+    pub route_via: String,
+    /// Names an area, with `{streets}` listing the streets along the boundary
+    pub area: String,
+    /// Names an area with no named streets along the boundary
+    pub unnamed_area: String,
+    /// Between items of a list, except the last two
+    pub list_separator: String,
+    /// Between the only two items of a list
+    pub list_pair_separator: String,
+    /// Between the last two items of a list with three or more
+    pub list_final_separator: String,
+    /// Used for any street without a name
+    pub unnamed_street: String,
+    /// Used for a freehand waypoint with nothing nearby
+    pub freehand_point: String,
+    /// Names a freehand waypoint near `{street}`
+    pub near_street: String,
+    /// Freehand waypoints this close to a node are named after the streets meeting there
+    pub nearby_node_meters: f64,
+    /// Freehand waypoints this close to a named street are named after it
+    pub nearby_street_meters: f64,
+}
+
+impl Default for Naming {
+    fn default() -> Self {
+        Self {
+            language: None,
+            route: "Route from {from} to {to}".to_string(),
+            route_via: "Route from {from} to {to} via {via}".to_string(),
+            area: "Area bounded by {streets}".to_string(),
+            unnamed_area: "Area".to_string(),
+            list_separator: ", ".to_string(),
+            list_pair_separator: " and ".to_string(),
+            list_final_separator: ", and ".to_string(),
+            unnamed_street: "???".to_string(),
+            freehand_point: "???".to_string(),
+            near_street: "near {street}".to_string(),
+            nearby_node_meters: 5.0,
+            nearby_street_meters: 50.0,
+        }
+    }
+}
+
+impl Naming {
+    /// Names a route. `via` may be empty.
+    pub fn route_name(&self, from: &str, to: &str, via: Vec<String>) -> String {
+        if via.is_empty() {
+            fill(&self.route, &[("from", from), ("to", to)])
+        } else {
+            let via = self.list(via);
+            fill(
+                &self.route_via,
+                &[("from", from), ("to", to), ("via", &via)],
+            )
+        }
+    }
+
+    /// Names an area from the streets along its boundary, which may be empty.
+    pub fn area_name(&self, streets: Vec<String>) -> String {
+        if streets.is_empty() {
+            self.unnamed_area.clone()
+        } else {
+            fill(&self.area, &[("streets", &self.list(streets))])
+        }
+    }
+
+    /// Names a freehand waypoint close to a street
+    pub fn near_street(&self, street: &str) -> String {
+        fill(&self.near_street, &[("street", street)])
+    }
+
+    /// Joins items into one list, like "A, B, and C"
+    pub fn list(&self, items: impl IntoIterator<Item = String>) -> String {
+        let items: Vec<String> = items.into_iter().collect();
+        let len = items.len();
+        let mut s = String::new();
+        for (idx, item) in items.into_iter().enumerate() {
+            if idx != 0 {
+                if idx == len - 1 {
+                    if len == 2 {
+                        s.push_str(&self.list_pair_separator);
+                    } else {
+                        s.push_str(&self.list_final_separator);
+                    }
+                } else {
+                    s.push_str(&self.list_separator);
+                }
+            }
+            s.push_str(&item);
+        }
+        s
+    }
+}
+
+// Replaces every `{key}` in the template. Values are inserted as they are, even if they contain
+// something that looks like a placeholder. Unknown placeholders are left alone.
+fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut result = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after.find('}').and_then(|end| {
+            values
+                .iter()
+                .find(|(key, _)| *key == &after[..end])
+                .map(|(_, value)| (*value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                result.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                result.push('{');
+                rest = after;
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
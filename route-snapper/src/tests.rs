@@ -1,5 +1,8 @@
 use crate::*;
 
+use geo::{line_measures::Densify, Euclidean, Translate, Validation};
+use route_snapper_graph::Turn;
+
 // The NodeIDs depend on the real southwark.bin graph! If the path between two nodes happens to
 // include a third node, then a test may be confusing, because it could look like an intermediate
 // point.
@@ -163,9 +166,14 @@ fn test_area() {
         vec![WAYPT1, WAYPT4, WAYPT2, WAYPT3, WAYPT1]
     );
 
-    // Due to a current limitation, we can't delete the first/last waypoint
+    // Deleting the first/last waypoint closes the area on the next one
     must_mouseover_waypt(&mut snapper, WAYPT1);
     snapper.on_click();
+    assert_eq!(
+        snapper.route.waypoints,
+        vec![WAYPT4, WAYPT2, WAYPT3, WAYPT4]
+    );
+    snapper.undo();
     assert_eq!(
         snapper.route.waypoints,
         vec![WAYPT1, WAYPT4, WAYPT2, WAYPT3, WAYPT1]
@@ -187,6 +195,1766 @@ fn test_area() {
     );
 }
 
+#[test]
+fn test_undo_redo() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+    snapper.router.config.extend_route = true;
+
+    for waypt in [WAYPT1, WAYPT2, WAYPT3] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    assert_eq!(snapper.history.undo_length(), 3);
+
+    snapper.undo();
+    snapper.undo();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1]);
+    assert_eq!(snapper.history.redo_length(), 2);
+    snapper.redo();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, WAYPT2]);
+
+    // A new change discards what could be redone
+    must_mouseover_waypt(&mut snapper, WAYPT4);
+    snapper.on_click();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, WAYPT2, WAYPT4]);
+    assert_eq!(snapper.history.redo_length(), 0);
+    snapper.redo();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, WAYPT2, WAYPT4]);
+
+    // Clicking a point without moving it isn't a step
+    let undo_length = snapper.history.undo_length();
+    must_mouseover_waypt(&mut snapper, WAYPT2);
+    snapper.on_drag_start();
+    snapper.on_mouse_up();
+    assert_eq!(snapper.history.undo_length(), undo_length);
+
+    // A whole drag is one step, no matter how many times the mouse moves
+    must_mouseover_waypt(&mut snapper, WAYPT2);
+    snapper.on_drag_start();
+    optionally_mouseover_waypt(&mut snapper, WAYPT3);
+    optionally_mouseover_waypt(&mut snapper, WAYPT5);
+    snapper.on_mouse_up();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, WAYPT5, WAYPT4]);
+    assert_eq!(snapper.history.undo_length(), undo_length + 1);
+    snapper.undo();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, WAYPT2, WAYPT4]);
+    snapper.redo();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, WAYPT5, WAYPT4]);
+
+    // Closing an area adds two waypoints in one step
+    snapper.clear_state();
+    snapper.set_area_mode();
+    for waypt in [WAYPT1, WAYPT2, WAYPT3] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    assert_eq!(
+        snapper.route.waypoints,
+        vec![WAYPT1, WAYPT2, WAYPT3, WAYPT1]
+    );
+    snapper.undo();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, WAYPT2]);
+    snapper.redo();
+    assert_eq!(
+        snapper.route.waypoints,
+        vec![WAYPT1, WAYPT2, WAYPT3, WAYPT1]
+    );
+}
+
+#[test]
+fn test_session() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+    snapper.set_area_mode();
+    for waypt in [WAYPT1, WAYPT2, WAYPT3] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    snapper.undo();
+    snapper.snap_mode = false;
+    snapper.set_repair_areas(true);
+    snapper.set_refuse_disconnected(true);
+    snapper.set_snap_within_component(true);
+    snapper
+        .set_naming(r#"{"unnamed_area": "Ardal"}"#.to_string())
+        .unwrap();
+
+    let mut restored = JsRouteSnapper::new(&map_bytes).unwrap();
+    restored.import_session(snapper.export_session()).unwrap();
+    assert_eq!(restored.route.waypoints, snapper.route.waypoints);
+    assert_eq!(restored.route.full_path, snapper.route.full_path);
+    assert_eq!(restored.get_config(), snapper.get_config());
+    assert!(!restored.snap_mode);
+    assert!(restored.repair_areas);
+    assert!(restored.refuse_disconnected);
+    assert!(restored.snap_within_component);
+    assert_eq!(restored.naming.unnamed_area, "Ardal");
+    assert_eq!(
+        restored.history.undo_length(),
+        snapper.history.undo_length()
+    );
+    assert_eq!(restored.history.redo_length(), 1);
+
+    // History behaves the same way
+    for s in [&mut snapper, &mut restored] {
+        s.redo();
+        s.undo();
+        s.undo();
+    }
+    assert_eq!(restored.route.waypoints, vec![WAYPT1]);
+    assert_eq!(restored.route.waypoints, snapper.route.waypoints);
+    assert_eq!(restored.export_session(), snapper.export_session());
+}
+
+#[test]
+fn test_multiple_routes() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+
+    for waypt in [WAYPT1, WAYPT2] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    let id = snapper.new_route();
+    assert_eq!(snapper.route_ids(), vec![0, id]);
+    assert!(snapper.route.waypoints.is_empty());
+    for waypt in [WAYPT3, WAYPT4] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+
+    // Each route has its own undo history
+    snapper.undo();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT3]);
+    snapper.set_active_route(0).unwrap();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, WAYPT2]);
+    snapper.undo();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1]);
+    snapper.redo();
+    snapper.set_active_route(id).unwrap();
+    snapper.redo();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT3, WAYPT4]);
+
+    // The other route is drawn differently
+    let gj: FeatureCollection = snapper.render_geojson().parse().unwrap();
+    assert!(gj.features.iter().any(|f| {
+        f.property("inactive") == Some(&serde_json::Value::Bool(true))
+            && f.property("route_id") == Some(&serde_json::Value::from(0))
+    }));
+
+    // Dragging a point on the inactive route switches to it
+    drag(&mut snapper, WAYPT2, WAYPT5);
+    assert_eq!(snapper.active_route(), 0);
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, WAYPT5]);
+    snapper.undo();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, WAYPT2]);
+
+    // Sessions include every route
+    let mut restored = JsRouteSnapper::new(&map_bytes).unwrap();
+    restored.import_session(snapper.export_session()).unwrap();
+    assert_eq!(restored.route_ids(), vec![0, id]);
+    assert_eq!(restored.active_route(), 0);
+    restored.set_active_route(id).unwrap();
+    assert_eq!(restored.route.waypoints, vec![WAYPT3, WAYPT4]);
+
+    // Deleting the active route switches to another
+    snapper.delete_route(0).unwrap();
+    assert_eq!(snapper.route_ids(), vec![id]);
+    assert_eq!(snapper.route.waypoints, vec![WAYPT3, WAYPT4]);
+}
+
+#[test]
+fn test_drag_line() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+    for waypt in [WAYPT1, WAYPT2] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+
+    // Find the middle of the longest line segment along the route
+    let mut longest: Option<(f64, Coord)> = None;
+    for entry in &snapper.route.full_path {
+        if let PathEntry::Edge(dir_edge) = entry {
+            for pair in edge_geometry(&snapper.router.map, *dir_edge).windows(2) {
+                let length = Haversine.distance(Point::from(pair[0]), Point::from(pair[1]));
+                if longest.map(|x| length > x.0).unwrap_or(true) {
+                    longest = Some((length, (pair[0] + pair[1]) / 2.0));
+                }
+            }
+        }
+    }
+    let (length, middle) = longest.unwrap();
+    assert!(length > 10.0);
+
+    let circle_radius_meters = 1.0;
+    snapper.on_mouse_move(middle.x, middle.y, circle_radius_meters);
+    assert!(matches!(snapper.mode, Mode::HoveringLine { .. }));
+    // Clicking the line does nothing
+    snapper.on_click();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, WAYPT2]);
+
+    // Dragging inserts a new waypoint in the middle
+    assert!(snapper.on_drag_start());
+    optionally_mouseover_waypt(&mut snapper, WAYPT5);
+    snapper.on_mouse_up();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, WAYPT5, WAYPT2]);
+
+    // And it's all one step to undo
+    snapper.undo();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, WAYPT2]);
+
+    // When the route can be extended, a node right beside the line can still be clicked
+    snapper.router.config.extend_route = true;
+    let circle_radius_meters = 15.0;
+    let (node, pt) = snapper
+        .snap_to_nodes
+        .nearest_neighbor_iter(&[middle.x, middle.y])
+        .map(|node| (node.data, snapper.router.map.node(node.data)))
+        .find(|(node, pt)| {
+            !snapper.route.contains(&PathEntry::SnappedPoint(*node))
+                && snapper
+                    .mouseover_route_line(*pt, circle_radius_meters)
+                    .is_some()
+        })
+        .unwrap();
+    snapper.on_mouse_move(pt.x, pt.y, circle_radius_meters);
+    assert_eq!(snapper.mode, Mode::Hovering(Waypoint::Snapped(node)));
+    snapper.on_click();
+    assert_eq!(
+        snapper.route.waypoints,
+        vec![WAYPT1, WAYPT2, Waypoint::Snapped(node)]
+    );
+}
+
+#[test]
+fn test_area_freehand() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+    snapper.set_area_mode();
+    let circle_radius_meters = 1.0;
+
+    // Start with a freehand point
+    let pt1 = unhash_pt(snapper.to_pt(WAYPT1));
+    let free1 = Coord {
+        x: pt1.x + 0.001,
+        y: pt1.y + 0.001,
+    };
+    snapper.toggle_snap_mode();
+    snapper.on_mouse_move(free1.x, free1.y, circle_radius_meters);
+    snapper.on_click();
+    snapper.toggle_snap_mode();
+    for waypt in [WAYPT2, WAYPT3] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    let free1 = Waypoint::Free(free1);
+    assert_eq!(snapper.route.waypoints, vec![free1, WAYPT2, WAYPT3, free1]);
+    assert!(snapper.route.is_closed_area());
+    assert!(snapper.into_polygon_area(&snapper.route).is_some());
+
+    // Once the area is closed, freehand mode doesn't add anything
+    snapper.toggle_snap_mode();
+    snapper.on_mouse_move(pt1.x, pt1.y, circle_radius_meters);
+    snapper.on_click();
+    assert_eq!(snapper.route.waypoints.len(), 4);
+    snapper.toggle_snap_mode();
+
+    // Drag the freehand point, which is both the first and last waypoint
+    let free2_pt = Coord {
+        x: pt1.x - 0.001,
+        y: pt1.y + 0.001,
+    };
+    drag(&mut snapper, free1, Waypoint::Free(free2_pt));
+    // The test helpers lose a bit of precision
+    let free2 = snapper.route.waypoints[0];
+    assert!(matches!(free2, Waypoint::Free(pt) if (pt.x - free2_pt.x).abs() < 1e-6));
+    assert_eq!(snapper.route.waypoints, vec![free2, WAYPT2, WAYPT3, free2]);
+
+    // Convert it to a snapped point while dragging
+    must_mouseover_waypt(&mut snapper, free2);
+    snapper.on_drag_start();
+    optionally_mouseover_waypt(&mut snapper, Waypoint::Free(pt1));
+    snapper.toggle_snap_mode();
+    snapper.on_mouse_up();
+    assert_eq!(
+        snapper.route.waypoints,
+        vec![WAYPT1, WAYPT2, WAYPT3, WAYPT1]
+    );
+
+    // And back to freehand
+    must_mouseover_waypt(&mut snapper, WAYPT2);
+    snapper.on_drag_start();
+    snapper.toggle_snap_mode();
+    snapper.on_mouse_up();
+    let free3 = Waypoint::Free(unhash_pt(snapper.to_pt(WAYPT2)));
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, free3, WAYPT3, WAYPT1]);
+
+    // Freehand points can be deleted too
+    snapper.undo();
+    snapper.undo();
+    assert_eq!(snapper.route.waypoints, vec![free2, WAYPT2, WAYPT3, free2]);
+    must_mouseover_waypt(&mut snapper, free2);
+    snapper.on_click();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT2, WAYPT3, WAYPT2]);
+}
+
+#[test]
+fn test_area_seam() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+    snapper.set_area_mode();
+    for waypt in [WAYPT1, WAYPT2, WAYPT3] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    let intermediate = find_intermediate_point(&snapper, WAYPT2, WAYPT3);
+    drag(&mut snapper, intermediate, WAYPT4);
+    assert_eq!(
+        snapper.route.waypoints,
+        vec![WAYPT1, WAYPT2, WAYPT4, WAYPT3, WAYPT1]
+    );
+
+    // Rotating keeps the same polygon
+    let edges = |snapper: &JsRouteSnapper| {
+        let mut edges: Vec<(EdgeID, bool)> = snapper
+            .route
+            .full_path
+            .iter()
+            .filter_map(|x| match x {
+                PathEntry::Edge(dir_edge) => Some((dir_edge.0, dir_edge.1)),
+                _ => None,
+            })
+            .collect();
+        edges.sort();
+        edges
+    };
+    let before = edges(&snapper);
+    snapper.rotate_area(2).unwrap();
+    assert_eq!(
+        snapper.route.waypoints,
+        vec![WAYPT4, WAYPT3, WAYPT1, WAYPT2, WAYPT4]
+    );
+    assert_eq!(edges(&snapper), before);
+    assert_eq!(snapper.route.full_path[0], WAYPT4.to_path_entry());
+    assert_eq!(
+        snapper.route.full_path.last(),
+        Some(&WAYPT4.to_path_entry())
+    );
+
+    // Moving the seam keeps the area closed
+    drag(&mut snapper, WAYPT4, WAYPT5);
+    assert_eq!(
+        snapper.route.waypoints,
+        vec![WAYPT5, WAYPT3, WAYPT1, WAYPT2, WAYPT5]
+    );
+
+    // Deleting it closes the area on the next waypoint, in one step
+    must_mouseover_waypt(&mut snapper, WAYPT5);
+    snapper.on_click();
+    assert_eq!(
+        snapper.route.waypoints,
+        vec![WAYPT3, WAYPT1, WAYPT2, WAYPT3]
+    );
+    assert!(snapper.route.is_closed_area());
+    snapper.undo();
+    assert_eq!(
+        snapper.route.waypoints,
+        vec![WAYPT5, WAYPT3, WAYPT1, WAYPT2, WAYPT5]
+    );
+
+    // But an area needs at least 3 waypoints
+    for _ in 0..2 {
+        let first = snapper.route.waypoints[0];
+        must_mouseover_waypt(&mut snapper, first);
+        snapper.on_click();
+    }
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, WAYPT2, WAYPT1]);
+    must_mouseover_waypt(&mut snapper, WAYPT1);
+    snapper.on_click();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, WAYPT2, WAYPT1]);
+}
+
+#[test]
+fn test_area_rings() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+    snapper.set_area_mode();
+    snapper.toggle_snap_mode();
+    let center = unhash_pt(snapper.to_pt(WAYPT1));
+    let draw_triangle = |snapper: &mut JsRouteSnapper, dx: f64, size: f64| {
+        for (x, y) in [(-size, -size), (size, -size), (0.0, size)] {
+            snapper.on_mouse_move(center.x + dx + x, center.y + y, 1.0);
+            snapper.on_click();
+        }
+        assert!(snapper.route.is_closed_area());
+    };
+    let polygons = |snapper: &JsRouteSnapper| -> Vec<Polygon> {
+        match geo::Geometry::try_from(snapper.into_polygon_area(&snapper.route).unwrap()).unwrap() {
+            geo::Geometry::Polygon(p) => vec![p],
+            geo::Geometry::MultiPolygon(mp) => mp.0,
+            x => panic!("unexpected {x:?}"),
+        }
+    };
+
+    // The outer ring
+    draw_triangle(&mut snapper, 0.0, 0.004);
+    let outer = snapper.route.waypoints.clone();
+
+    // A ring inside becomes a hole
+    snapper.start_new_ring().unwrap();
+    assert!(snapper.route.waypoints.is_empty());
+    draw_triangle(&mut snapper, 0.0, 0.001);
+    let hole = snapper.route.waypoints.clone();
+    let result = polygons(&snapper);
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0].interiors().len(), 1);
+
+    // A ring outside becomes another part
+    snapper.start_new_ring().unwrap();
+    draw_triangle(&mut snapper, 0.02, 0.002);
+    let part = snapper.route.waypoints.clone();
+    let result = polygons(&snapper);
+    assert_eq!(result.len(), 2);
+    assert_eq!(result.iter().map(|p| p.interiors().len()).sum::<usize>(), 1);
+
+    // Undoing goes back through the rings
+    while !snapper.route.waypoints.is_empty() {
+        snapper.undo();
+    }
+    assert_eq!(
+        snapper.route.snapshot(),
+        vec![vec![], outer.clone(), hole.clone()]
+    );
+    snapper.undo();
+    assert_eq!(snapper.route.snapshot(), vec![hole.clone(), outer.clone()]);
+    snapper.redo();
+    snapper.redo();
+    snapper.redo();
+    snapper.redo();
+    assert_eq!(
+        snapper.route.snapshot(),
+        vec![part.clone(), outer.clone(), hole.clone()]
+    );
+
+    // Dragging a point on another ring starts editing that ring
+    let moved = Coord {
+        x: center.x - 0.005,
+        y: center.y - 0.004,
+    };
+    snapper.toggle_snap_mode();
+    drag(&mut snapper, outer[0], Waypoint::Free(moved));
+    assert_eq!(snapper.route.waypoints.len(), 4);
+    assert!(
+        matches!(snapper.route.waypoints[0], Waypoint::Free(pt) if (pt.x - moved.x).abs() < 1e-6)
+    );
+    assert_eq!(snapper.route.waypoints[1], outer[1]);
+    assert_eq!(snapper.route.other_rings.len(), 2);
+    assert_eq!(polygons(&snapper).len(), 2);
+
+    // The final feature can be edited again
+    let feature: Feature = snapper.to_final_feature().unwrap().parse().unwrap();
+    let get = |key: &str| feature.property(key).unwrap().clone();
+    let mut rings: Vec<Vec<RouteWaypoint>> =
+        vec![serde_json::from_value(get("waypoints")).unwrap()];
+    rings.extend(serde_json::from_value::<Vec<Vec<RouteWaypoint>>>(get("other_rings")).unwrap());
+    assert_eq!(rings.len(), 3);
+    let before = polygons(&snapper);
+    snapper.load_rings(rings).unwrap();
+    assert_eq!(snapper.route.other_rings.len(), 2);
+    let after = polygons(&snapper);
+    assert_eq!(after.len(), before.len());
+    for (p1, p2) in before.iter().zip(after.iter()) {
+        assert!((p1.unsigned_area() - p2.unsigned_area()).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn test_area_validity() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+    snapper.set_area_mode();
+    snapper.toggle_snap_mode();
+    let center = unhash_pt(snapper.to_pt(WAYPT1));
+    let offset = |x: f64, y: f64| Coord {
+        x: center.x + 0.002 * x,
+        y: center.y + 0.002 * y,
+    };
+    for pt in [offset(0.0, 0.0), offset(1.0, 0.0), offset(0.5, 1.0)] {
+        snapper.on_mouse_move(pt.x, pt.y, 1.0);
+        snapper.on_click();
+    }
+    assert!(snapper.area_problems(&snapper.route).is_empty());
+
+    // Drag the first side across the second
+    let middle = offset(0.5, 0.0);
+    snapper.on_mouse_move(middle.x, middle.y, 1.0);
+    assert!(matches!(snapper.mode, Mode::HoveringLine { .. }));
+    snapper.on_drag_start();
+    let crossing = offset(1.5, 0.5);
+    snapper.on_mouse_move(crossing.x, crossing.y, 1.0);
+    snapper.on_mouse_up();
+    assert_eq!(snapper.route.waypoints.len(), 5);
+    let problems = snapper.area_problems(&snapper.route);
+    assert_eq!(problems.len(), 1);
+    assert_eq!(problems[0].0, validity::Problem::SelfIntersection);
+    assert_eq!(problems[0].1.len(), 2);
+
+    // Repairing splits it into two valid parts
+    snapper.set_repair_areas(true);
+    let Some(geometry) = snapper.into_polygon_area(&snapper.route) else {
+        panic!("no polygon");
+    };
+    let geo::Geometry::MultiPolygon(mp) = geo::Geometry::<f64>::try_from(geometry).unwrap() else {
+        panic!("not a MultiPolygon");
+    };
+    assert_eq!(mp.0.len(), 2);
+    assert!(mp.is_valid());
+
+    // A spike along one street
+    let a = Coord { x: 0.0, y: 0.0 };
+    let b = Coord { x: 0.001, y: 0.0 };
+    let c = Coord { x: 0.001, y: 0.001 };
+    let spike1 = Coord { x: 0.002, y: 0.0 };
+    let spike2 = Coord { x: 0.003, y: 0.0 };
+    let ring = LineString::new(vec![a, b, spike1, spike2, spike1, b, c, a]);
+    let problems = validity::find_problems(&ring);
+    assert_eq!(problems.len(), 1);
+    assert_eq!(problems[0].0, validity::Problem::RepeatedSegment);
+    assert_eq!(problems[0].1.len(), 4);
+    let repaired = validity::remove_spikes(&ring);
+    assert_eq!(repaired, LineString::new(vec![a, b, c, a]));
+    assert!(validity::find_problems(&repaired).is_empty());
+
+    // Nothing enclosed
+    let ring = LineString::new(vec![a, b, a]);
+    assert_eq!(
+        validity::find_problems(&ring)[0].0,
+        validity::Problem::ZeroArea
+    );
+    assert!(validity::make_valid(vec![Polygon::new(ring, Vec::new())]).is_empty());
+}
+
+#[test]
+fn test_area_from_point() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+    snapper.set_area_mode();
+
+    let pt1 = unhash_pt(snapper.to_pt(WAYPT1));
+    let click1 = Coord {
+        x: pt1.x + 0.0003,
+        y: pt1.y + 0.0003,
+    };
+    snapper.area_from_point(click1.x, click1.y, false).unwrap();
+    assert!(snapper.route.is_closed_area());
+    assert!(snapper
+        .route
+        .waypoints
+        .iter()
+        .all(|waypt| matches!(waypt, Waypoint::Snapped(_))));
+    let index = faces::FaceIndex::new(&snapper.router.map);
+    let face1 =
+        faces::enclosing_face(&snapper.router.map, &index, &snapper.edge_segments, click1).unwrap();
+    let block1 = faces::face_polygon(&snapper.router.map, &face1);
+    assert!(block1.contains(&Point::from(click1)));
+    let area1 = polygon_area(&snapper);
+    assert!((area1 - block1.unsigned_area()).abs() < 1e-12);
+
+    // Click just across one side of the block to merge with its neighbor
+    let line = edge_geometry(&snapper.router.map, face1[0]);
+    let (from, to) = (line[0], line[1]);
+    let click2 = Coord {
+        x: (from.x + to.x) / 2.0 + (to.y - from.y) * 0.01,
+        y: (from.y + to.y) / 2.0 - (to.x - from.x) * 0.01,
+    };
+    let face2 =
+        faces::enclosing_face(&snapper.router.map, &index, &snapper.edge_segments, click2).unwrap();
+    let block2 = faces::face_polygon(&snapper.router.map, &face2);
+    snapper.area_from_point(click2.x, click2.y, true).unwrap();
+    assert!(snapper.route.is_closed_area());
+    let merged = polygon_area(&snapper);
+    assert!((merged - (block1.unsigned_area() + block2.unsigned_area())).abs() < 1e-12);
+
+    // The final feature describes the area. The street between the blocks is now inside.
+    let feature: Feature = snapper.to_final_feature().unwrap().parse().unwrap();
+    let get = |key: &str| feature.property(key).unwrap().clone();
+    let area_m2 = get("area_square_meters").as_f64().unwrap();
+    assert!(area_m2 > 1000.0 && area_m2 < 1_000_000.0);
+    assert!(get("perimeter_meters").as_f64().unwrap() > 100.0);
+    assert!(get("route_name")
+        .as_str()
+        .unwrap()
+        .starts_with("Area bounded by "));
+    let edges: Vec<usize> = serde_json::from_value(get("enclosed_edge_ids")).unwrap();
+    assert!(edges.contains(&(face1[0].0 .0 as usize)));
+    let nodes: Vec<usize> = serde_json::from_value(get("enclosed_node_ids")).unwrap();
+    for node in nodes {
+        assert!(!snapper
+            .route
+            .waypoints
+            .contains(&Waypoint::Snapped(NodeID(node as u32))));
+    }
+
+    // That was one step
+    snapper.undo();
+    assert!((polygon_area(&snapper) - area1).abs() < 1e-12);
+
+    // Without merging, the area is replaced
+    snapper.area_from_point(click2.x, click2.y, false).unwrap();
+    assert!((polygon_area(&snapper) - block2.unsigned_area()).abs() < 1e-12);
+}
+
+fn polygon_area(snapper: &JsRouteSnapper) -> f64 {
+    let geometry = snapper.into_polygon_area(&snapper.route).unwrap();
+    geo::Geometry::<f64>::try_from(geometry)
+        .unwrap()
+        .unsigned_area()
+}
+
+#[test]
+fn test_legs() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+    snapper.router.config.extend_route = true;
+    for waypt in [WAYPT1, WAYPT2, WAYPT3] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    // End with a freehand point
+    let pt3 = unhash_pt(snapper.to_pt(WAYPT3));
+    snapper.toggle_snap_mode();
+    snapper.on_mouse_move(pt3.x + 0.001, pt3.y, 1.0);
+    snapper.on_click();
+    assert_eq!(snapper.route.waypoints.len(), 4);
+
+    let feature: Feature = snapper.to_final_feature().unwrap().parse().unwrap();
+    let legs = feature
+        .property("legs")
+        .unwrap()
+        .as_array()
+        .unwrap()
+        .clone();
+    assert_eq!(legs.len(), 3);
+    for (idx, leg) in legs.iter().enumerate() {
+        assert_eq!(leg["from_waypoint"], idx);
+        assert_eq!(leg["to_waypoint"], idx + 1);
+    }
+
+    // Routed legs have a cost and streets
+    assert_eq!(legs[0]["routed"], true);
+    assert!((legs[0]["cost"].as_f64().unwrap() - path_cost(&snapper, WAYPT1, WAYPT2)).abs() < 1e-6);
+    assert!(!legs[0]["street_names"].as_array().unwrap().is_empty());
+
+    // The freehand leg is a straight line
+    assert_eq!(legs[2]["routed"], false);
+    assert!(legs[2]["cost"].is_null());
+    assert!(legs[2]["street_names"].as_array().unwrap().is_empty());
+
+    // The legs add up to the whole route
+    let total: f64 = legs
+        .iter()
+        .map(|leg| leg["length_meters"].as_f64().unwrap())
+        .sum();
+    let length = feature.property("length_meters").unwrap().as_f64().unwrap();
+    assert!((total - length).abs() < 1e-6);
+}
+
+#[test]
+fn test_directions() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+    snapper.router.config.extend_route = true;
+    for waypt in [WAYPT1, WAYPT2, WAYPT3] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    let pt3 = unhash_pt(snapper.to_pt(WAYPT3));
+    snapper.toggle_snap_mode();
+    snapper.on_mouse_move(pt3.x + 0.001, pt3.y, 1.0);
+    snapper.on_click();
+
+    let steps: Vec<serde_json::Value> = serde_json::from_str(&snapper.get_directions()).unwrap();
+    let maneuvers: Vec<&str> = steps
+        .iter()
+        .map(|step| step["maneuver"].as_str().unwrap())
+        .collect();
+    assert_eq!(maneuvers[0], "depart");
+    assert_eq!(maneuvers[maneuvers.len() - 2], "straight_line");
+    assert_eq!(maneuvers[maneuvers.len() - 1], "arrive");
+    assert!(steps[0]["instruction"]
+        .as_str()
+        .unwrap()
+        .starts_with("Continue"));
+
+    // Streets with the same name are merged, and the steps cover the whole route
+    for pair in steps.windows(2) {
+        if pair[0]["name"].is_string() && pair[0]["name"] == pair[1]["name"] {
+            assert_eq!(pair[1]["maneuver"], "u_turn");
+        }
+    }
+    let total: f64 = steps
+        .iter()
+        .map(|step| step["length_meters"].as_f64().unwrap())
+        .sum();
+    let feature: Feature = snapper.to_final_feature().unwrap().parse().unwrap();
+    let length = feature.property("length_meters").unwrap().as_f64().unwrap();
+    assert!((total - length).abs() < 1.0);
+
+    assert_eq!(Turn::from_degrees(5.0), Turn::Straight);
+    assert_eq!(Turn::from_degrees(-90.0), Turn::Left);
+    assert_eq!(Turn::from_degrees(140.0), Turn::SharpRight);
+    assert_eq!(Turn::from_degrees(-175.0), Turn::UTurn);
+}
+
+#[test]
+fn test_street_summary() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+    for waypt in [WAYPT1, WAYPT2] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+
+    let feature: Feature = snapper.to_final_feature().unwrap().parse().unwrap();
+    let summary = feature
+        .property("street_summary")
+        .unwrap()
+        .as_array()
+        .unwrap()
+        .clone();
+    let names: Vec<&str> = summary
+        .iter()
+        .map(|street| street["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names.iter().collect::<BTreeSet<_>>().len(), names.len());
+
+    // Every named edge is counted once
+    let named_length: f64 = snapper
+        .route
+        .full_path
+        .iter()
+        .filter_map(|entry| match entry {
+            PathEntry::Edge(e) => Some(snapper.router.map.edge(e.0)),
+            _ => None,
+        })
+        .filter(|edge| edge.name.is_some())
+        .map(|edge| edge.length_meters)
+        .sum();
+    let total: f64 = summary
+        .iter()
+        .map(|street| street["length_meters"].as_f64().unwrap())
+        .sum();
+    assert!((total - named_length).abs() < 1e-6);
+
+    // The name mentions the longest streets, in order
+    let route_name = feature.property("route_name").unwrap().as_str().unwrap();
+    let (_, via) = route_name.split_once(" via ").unwrap();
+    let via: Vec<&str> = via.split(" and ").collect();
+    assert!(!via.is_empty() && via.len() <= MAX_VIA_STREETS);
+    let positions: Vec<usize> = via
+        .iter()
+        .map(|name| names.iter().position(|x| x == name).unwrap())
+        .collect();
+    assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+fn test_naming() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+    for waypt in [WAYPT1, WAYPT2] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    let route_name = |snapper: &JsRouteSnapper| {
+        let feature: Feature = snapper.to_final_feature().unwrap().parse().unwrap();
+        feature
+            .property("route_name")
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .to_string()
+    };
+    let english = route_name(&snapper);
+    assert!(english.starts_with("Route from "));
+
+    snapper
+        .set_naming(
+            r#"{
+              "route": "Llwybr o {from} i {to}",
+              "route_via": "Llwybr o {from} i {to} drwy {via}",
+              "list_pair_separator": " a ",
+              "list_final_separator": " a "
+            }"#
+            .to_string(),
+        )
+        .unwrap();
+    let welsh = route_name(&snapper);
+    assert!(welsh.starts_with("Llwybr o "));
+    assert!(welsh.contains(" drwy "));
+    assert!(!welsh.contains(" and "));
+    // Fields not given keep their defaults
+    assert_eq!(snapper.naming.unnamed_area, "Area");
+    // Names that look like placeholders aren't replaced again
+    assert_eq!(
+        Naming::default().route_name("{to} Street", "{via}", Vec::new()),
+        "Route from {to} Street to {via}"
+    );
+
+    // Prefer names in another language when the graph has them
+    let edge = snapper
+        .route
+        .full_path
+        .iter()
+        .find_map(|entry| match entry {
+            PathEntry::Edge(e) if snapper.router.map.edge(e.0).name.is_some() => Some(e.0),
+            _ => None,
+        })
+        .unwrap();
+    let mut names = vec![BTreeMap::new(); snapper.router.map.edges.len()];
+    names[edge.0 as usize].insert("cy".to_string(), "Heol Prawf".to_string());
+    snapper.router.map.localized_names = names;
+    snapper
+        .set_naming(r#"{"language": "cy"}"#.to_string())
+        .unwrap();
+    let feature: Feature = snapper.to_final_feature().unwrap().parse().unwrap();
+    let summary = feature
+        .property("street_summary")
+        .unwrap()
+        .as_array()
+        .unwrap();
+    assert!(summary.iter().any(|street| street["name"] == "Heol Prawf"));
+
+    // The names survive saving and loading the graph
+    let bytes = bincode::serialize(&snapper.router.map).unwrap();
+    let mut map: RouteSnapperMap = bincode::deserialize(&bytes).unwrap();
+    map.finalize().unwrap();
+    assert_eq!(map.edge_name(edge, Some("cy")), Some("Heol Prawf"));
+    assert_eq!(
+        map.edge_name(edge, Some("es")),
+        map.edge(edge).name.as_deref()
+    );
+
+    // Older files end before the names, but names that can't be read are an error
+    map.localized_names.clear();
+    let mut bytes = bincode::serialize(&map).unwrap();
+    bytes.truncate(bytes.len() - 8);
+    let old: RouteSnapperMap = bincode::deserialize(&bytes).unwrap();
+    assert!(old.localized_names.is_empty());
+    // One edge with one name, whose key isn't UTF-8
+    for x in [1_u64, 1, 1] {
+        bytes.extend_from_slice(&x.to_le_bytes());
+    }
+    bytes.push(0xff);
+    assert!(bincode::deserialize::<RouteSnapperMap>(&bytes).is_err());
+}
+
+#[test]
+fn test_name_freehand_points() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+
+    let edge = snapper
+        .router
+        .map
+        .edges
+        .iter()
+        .find(|edge| edge.name.is_some() && edge.length_meters > 100.0)
+        .unwrap();
+
+    // Right next to a node, use the same name as snapping to it
+    let pt1 = snapper.router.map.node(edge.node1);
+    let snapped_name = snapper.name_waypoint(&Waypoint::Snapped(edge.node1));
+    assert_ne!(snapped_name, "???");
+    let near_node = Waypoint::Free(Coord {
+        x: pt1.x + 0.00001,
+        y: pt1.y,
+    });
+    assert_eq!(snapper.name_waypoint(&near_node), snapped_name);
+
+    // Partway along a named street
+    let middle = edge
+        .geometry
+        .point_at_ratio_from_start(&Euclidean, 0.5)
+        .unwrap()
+        .translate(0.0, 0.0001);
+    let name = snapper.name_waypoint(&Waypoint::Free(middle.into()));
+    assert!(name.starts_with("near "), "{name}");
+
+    // Nothing nearby
+    let far_away = Waypoint::Free(Coord {
+        x: pt1.x + 0.05,
+        y: pt1.y + 0.05,
+    });
+    assert_eq!(snapper.name_waypoint(&far_away), "???");
+}
+
+#[test]
+fn test_gpx() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+    snapper.router.config.extend_route = true;
+    for waypt in [WAYPT1, WAYPT2] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    let pt2 = unhash_pt(snapper.to_pt(WAYPT2));
+    snapper.toggle_snap_mode();
+    snapper.on_mouse_move(pt2.x + 0.001, pt2.y, 1.0);
+    snapper.on_click();
+    let original = snapper.route.waypoints.clone();
+    assert!(matches!(original[2], Waypoint::Free(_)));
+
+    let output = snapper.to_gpx().unwrap();
+    let points = gpx::read_route_points(&output).unwrap();
+    assert_eq!(points.len(), 3);
+    assert_eq!(
+        points[0].name.as_ref().unwrap(),
+        &snapper.name_waypoint(&WAYPT1)
+    );
+    assert!(output.contains("<trkpt"));
+
+    // Importing restores the same waypoints, snapping only what's close to the network
+    let mut imported = JsRouteSnapper::new(&map_bytes).unwrap();
+    imported.import_gpx(output, 1.0).unwrap();
+    assert_eq!(imported.route.waypoints.len(), 3);
+    assert_eq!(imported.route.waypoints[..2], original[..2]);
+    match (imported.route.waypoints[2], original[2]) {
+        (Waypoint::Free(pt1), Waypoint::Free(pt2)) => {
+            assert!((pt1.x - pt2.x).abs() < 1e-6 && (pt1.y - pt2.y).abs() < 1e-6);
+        }
+        _ => panic!("The last waypoint should be freehand"),
+    }
+
+    // Names are escaped, and files with only waypoints work too
+    let output = gpx::write_gpx(&[gpx::GpxRoute {
+        name: "A & B".to_string(),
+        waypoints: vec![gpx::GpxPoint {
+            pt: Coord { x: -0.1, y: 51.5 },
+            name: Some("<Mill & Lane>".to_string()),
+        }],
+        track: Vec::new(),
+        properties: Vec::new(),
+    }]);
+    let points = gpx::read_route_points(&output).unwrap();
+    assert_eq!(points[0].name.as_deref(), Some("<Mill & Lane>"));
+    let points = gpx::read_route_points(
+        r#"<gpx><wpt lon="-0.1" lat='51.5'><name><![CDATA[Start]]></name></wpt></gpx>"#,
+    )
+    .unwrap();
+    assert_eq!(
+        points,
+        vec![gpx::GpxPoint {
+            pt: Coord { x: -0.1, y: 51.5 },
+            name: Some("Start".to_string()),
+        }]
+    );
+
+    // Comments, CDATA, namespace prefixes, and character references are all handled
+    let points = gpx::read_route_points(
+        r#"<?xml version="1.0"?>
+        <!-- <rte><rtept lat="0" lon="0"></rtept></rte> -->
+        <g:gpx xmlns:g="http://www.topografix.com/GPX/1/1">
+          <g:desc><![CDATA[<rte>]]></g:desc>
+          <g:rte>
+            <g:rtept g:lat="51.5" g:lon="-0.1"><g:name>St Mary&#39;s &#x2192; &amp;c</g:name></g:rtept>
+          </g:rte>
+        </g:gpx>"#,
+    )
+    .unwrap();
+    assert_eq!(
+        points,
+        vec![gpx::GpxPoint {
+            pt: Coord { x: -0.1, y: 51.5 },
+            name: Some("St Mary's \u{2192} &c".to_string()),
+        }]
+    );
+
+    // Errors can be handled outside of WASM
+    assert!(imported.try_import_gpx("<gpx></gpx>", 1.0).is_err());
+    assert!(imported.try_import_gpx("<gpx><rte></gpx>", 1.0).is_err());
+    assert!(imported
+        .try_import_gpx(r#"<gpx><wpt lon="-0.1" lat="51.5">"#, 1.0)
+        .is_err());
+    // Coordinates must be real numbers in range
+    for (attributes, error) in [
+        (r#"lon="NaN" lat="51.5""#, "Bad lon NaN"),
+        (r#"lon="-0.1" lat="inf""#, "Bad lat inf"),
+        (r#"lon="-181" lat="51.5""#, "Bad lon -181"),
+        (r#"lon="-0.1" lat="91""#, "Bad lat 91"),
+    ] {
+        let input = format!("<gpx><wpt {attributes}/></gpx>");
+        assert_eq!(imported.try_import_gpx(&input, 1.0), Err(error.to_string()));
+    }
+    assert_eq!(imported.route.waypoints.len(), 3);
+}
+
+#[test]
+fn test_export() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+    for waypt in [WAYPT1, WAYPT2] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    let feature: Feature = snapper.to_final_feature().unwrap().parse().unwrap();
+    let properties = feature.properties.unwrap();
+
+    // Every format keeps the same properties
+    let output = snapper.export_routes("geojson", false).unwrap();
+    let fc: FeatureCollection = String::from_utf8(output).unwrap().parse().unwrap();
+    assert_eq!(fc.features.len(), 1);
+    assert_eq!(fc.features[0].properties.as_ref().unwrap(), &properties);
+
+    let output = snapper.export_routes("polyline6", false).unwrap();
+    let polylines: Vec<serde_json::Map<String, serde_json::Value>> =
+        serde_json::from_slice(&output).unwrap();
+    assert_eq!(polylines[0]["length_meters"], properties["length_meters"]);
+    assert_eq!(polylines[0]["waypoints"], properties["waypoints"]);
+    assert_eq!(polylines[0]["precision"], 6);
+    // The example from Google's documentation
+    assert_eq!(
+        export::encode_polyline(
+            &[
+                Coord { x: -120.2, y: 38.5 },
+                Coord {
+                    x: -120.95,
+                    y: 40.7
+                },
+                Coord {
+                    x: -126.453,
+                    y: 43.252
+                }
+            ],
+            5
+        ),
+        "_p~iF~ps|U_ulLnnqC_mqNvxq`@"
+    );
+
+    let kml = String::from_utf8(snapper.export_routes("kml", false).unwrap()).unwrap();
+    assert_eq!(kml.matches("<Placemark>").count(), 3);
+    assert!(kml.contains(r#"<Data name="length_meters">"#));
+    assert!(kml.contains(&format!(
+        "<name>{}</name>",
+        gpx::escape(&snapper.name_waypoint(&WAYPT1))
+    )));
+
+    let output = String::from_utf8(snapper.export_routes("gpx", false).unwrap()).unwrap();
+    assert_eq!(output, snapper.to_gpx().unwrap());
+    assert_eq!(gpx::read_route_points(&output).unwrap().len(), 2);
+    assert!(output.contains(&format!(
+        r#"<rs:property name="route_name">{}</rs:property>"#,
+        gpx::escape(properties["route_name"].as_str().unwrap())
+    )));
+
+    // FlatGeobuf exports every route, skipping unfinished ones
+    snapper.new_route();
+    must_mouseover_waypt(&mut snapper, WAYPT3);
+    snapper.on_click();
+    snapper.new_route();
+    for waypt in [WAYPT4, WAYPT5] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    let fgb = snapper.export_routes("flatgeobuf", true).unwrap();
+    let features = read_flatgeobuf(&fgb);
+    let output = snapper.export_routes("geojson", true).unwrap();
+    let fc: FeatureCollection = String::from_utf8(output).unwrap().parse().unwrap();
+    assert_eq!(features.len(), 2);
+    for ((line, properties), expected) in features.into_iter().zip(fc.features) {
+        let expected_line: LineString = expected.geometry.unwrap().try_into().unwrap();
+        assert_eq!(line, expected_line);
+        let expected_properties = expected.properties.unwrap();
+        assert_eq!(
+            properties.keys().collect::<Vec<_>>(),
+            expected_properties.keys().collect::<Vec<_>>()
+        );
+        for (key, value) in properties {
+            // Numbers all become doubles
+            match value.as_f64() {
+                Some(x) => assert_eq!(Some(x), expected_properties[&key].as_f64()),
+                None => assert_eq!(value, expected_properties[&key]),
+            }
+        }
+    }
+}
+
+// Reads the LineStrings and properties from a FlatGeobuf file, following
+// https://github.com/flatgeobuf/flatgeobuf/blob/master/src/fbs/. Like a FlatBuffers verifier, this
+// checks that every scalar is aligned. Every buffer must start aligned in the file, so checking
+// alignment in the file also checks it from the start of each buffer's size prefix.
+fn read_flatgeobuf(file: &[u8]) -> Vec<(LineString, serde_json::Map<String, serde_json::Value>)> {
+    fn read<const N: usize>(file: &[u8], pos: usize) -> [u8; N] {
+        assert_eq!(pos % N, 0, "Unaligned {N} byte value at {pos}");
+        file[pos..pos + N].try_into().unwrap()
+    }
+    fn u32_at(file: &[u8], pos: usize) -> usize {
+        u32::from_le_bytes(read(file, pos)) as usize
+    }
+    // Where a field of a table is, if it's present
+    fn field(file: &[u8], table: usize, id: usize) -> Option<usize> {
+        let vtable = (table as i64 - i32::from_le_bytes(read(file, table)) as i64) as usize;
+        let vtable_len = u16::from_le_bytes(read(file, vtable)) as usize;
+        if 4 + 2 * id >= vtable_len {
+            return None;
+        }
+        let offset = u16::from_le_bytes(read(file, vtable + 4 + 2 * id)) as usize;
+        (offset != 0).then_some(table + offset)
+    }
+    // Follows an offset field to a table, string, or vector
+    fn target(file: &[u8], table: usize, id: usize) -> Option<usize> {
+        let pos = field(file, table, id)?;
+        Some(pos + u32_at(file, pos))
+    }
+    // The position and length of a vector's elements
+    fn vector(file: &[u8], table: usize, id: usize) -> (usize, usize) {
+        let pos = target(file, table, id).unwrap();
+        (pos + 4, u32_at(file, pos))
+    }
+    // The root table of a size-prefixed buffer, and where the next buffer starts
+    fn root(file: &[u8], start: usize) -> (usize, usize) {
+        assert_eq!(start % 8, 0, "Buffer at {start} isn't aligned");
+        let size = u32_at(file, start);
+        (start + 4 + u32_at(file, start + 4), start + 4 + size)
+    }
+
+    assert_eq!(file[..8], [b'f', b'g', b'b', 3, b'f', b'g', b'b', 0]);
+    let (header, mut next) = root(file, 8);
+    let num_features = u64::from_le_bytes(read(file, field(file, header, 8).unwrap()));
+    let (columns_pos, num_columns) = vector(file, header, 7);
+    let columns: Vec<(String, u8)> = (0..num_columns)
+        .map(|idx| {
+            let slot = columns_pos + 4 * idx;
+            let column = slot + u32_at(file, slot);
+            let name = target(file, column, 0).unwrap();
+            let name = &file[name + 4..name + 4 + u32_at(file, name)];
+            let column_type = field(file, column, 1).map(|pos| file[pos]).unwrap_or(0);
+            (String::from_utf8(name.to_vec()).unwrap(), column_type)
+        })
+        .collect();
+
+    let mut features = Vec::new();
+    while next < file.len() {
+        let (feature, after) = root(file, next);
+        next = after;
+
+        let geometry = target(file, feature, 0).unwrap();
+        assert_eq!(field(file, geometry, 6).map(|pos| file[pos]), Some(2));
+        let (xy, len) = vector(file, geometry, 1);
+        let pts: Vec<Coord> = (0..len / 2)
+            .map(|idx| Coord {
+                x: f64::from_le_bytes(read(file, xy + 16 * idx)),
+                y: f64::from_le_bytes(read(file, xy + 16 * idx + 8)),
+            })
+            .collect();
+
+        let mut properties = serde_json::Map::new();
+        let (mut pos, len) = vector(file, feature, 1);
+        let end = pos + len;
+        while pos < end {
+            let (name, column_type) =
+                &columns[u16::from_le_bytes([file[pos], file[pos + 1]]) as usize];
+            pos += 2;
+            let value = match column_type {
+                2 => {
+                    pos += 1;
+                    serde_json::Value::from(file[pos - 1] != 0)
+                }
+                10 => {
+                    pos += 8;
+                    serde_json::Value::from(f64::from_le_bytes(
+                        file[pos - 8..pos].try_into().unwrap(),
+                    ))
+                }
+                11 | 12 => {
+                    let len = u32::from_le_bytes(file[pos..pos + 4].try_into().unwrap()) as usize;
+                    pos += 4 + len;
+                    let text = std::str::from_utf8(&file[pos - len..pos]).unwrap();
+                    if *column_type == 11 {
+                        serde_json::Value::from(text)
+                    } else {
+                        serde_json::from_str(text).unwrap()
+                    }
+                }
+                x => panic!("Unexpected column type {x}"),
+            };
+            properties.insert(name.clone(), value);
+        }
+        features.push((LineString::new(pts), properties));
+    }
+    assert_eq!(next, file.len());
+    assert_eq!(features.len() as u64, num_features);
+    features
+}
+
+#[test]
+fn test_match_trace() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+    for waypt in [WAYPT1, WAYPT3] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    let original = snapper.entire_line_string(&snapper.route).unwrap();
+    let original_length = original.length(&Haversine);
+
+    // Make a noisy trace along the route, with a point every 15m wobbling a few meters to each side
+    let mut trace: Vec<Coord> = Haversine.densify(&original, 15.0).0;
+    for (idx, pt) in trace.iter_mut().enumerate() {
+        pt.y += if idx % 2 == 0 { 0.00003 } else { -0.00003 };
+    }
+    let input = serde_json::to_string(&Geometry::from(&LineString::new(trace.clone()))).unwrap();
+
+    let mut matched = JsRouteSnapper::new(&map_bytes).unwrap();
+    let quality: serde_json::Value =
+        serde_json::from_str(&matched.match_trace(input, 5.0).unwrap()).unwrap();
+    assert_eq!(quality["matched_fraction"], 1.0);
+    assert!(quality["mean_distance_meters"].as_f64().unwrap() < 5.0);
+    assert!(matched
+        .route
+        .waypoints
+        .iter()
+        .all(|waypt| matches!(waypt, Waypoint::Snapped(_))));
+    assert!(matched.route.waypoints.len() < trace.len() / 4);
+    let length = matched
+        .entire_line_string(&matched.route)
+        .unwrap()
+        .length(&Haversine);
+    assert!((length - original_length).abs() < 0.1 * original_length);
+
+    // Leaving the network ends with freehand points, and GPX tracks work too
+    let last = *trace.last().unwrap();
+    trace.push(Coord {
+        x: last.x + 0.1,
+        y: last.y,
+    });
+    let input = gpx::write_gpx(&[gpx::GpxRoute {
+        name: "Trace".to_string(),
+        waypoints: Vec::new(),
+        track: vec![trace],
+        properties: Vec::new(),
+    }]);
+    let quality: serde_json::Value =
+        serde_json::from_str(&matched.match_trace(input, 5.0).unwrap()).unwrap();
+    assert!(quality["matched_fraction"].as_f64().unwrap() < 1.0);
+    assert!(matches!(
+        matched.route.waypoints.last(),
+        Some(Waypoint::Free(_))
+    ));
+
+    // Every point must be a real coordinate
+    let nan_point = r#"<gpx><trk><trkseg><trkpt lon="-0.1" lat="51.5"/><trkpt lon="NaN" lat="51.5"/></trkseg></trk></gpx>"#;
+    for (input, error) in [
+        (nan_point, "Bad lon NaN"),
+        (
+            r#"{"type": "LineString", "coordinates": [[-0.1, 51.5], [200.0, 51.5]]}"#,
+            "Bad trace point 200, 51.5",
+        ),
+    ] {
+        assert_eq!(matching::read_trace(input), Err(error.to_string()));
+    }
+
+    // Without a route line, distances are unknown rather than zero
+    let short_trace = vec![Coord { x: -0.1, y: 51.5 }, Coord { x: -0.1, y: 51.6 }];
+    for route in [None, Some(&LineString::new(vec![short_trace[0]]))] {
+        let quality = matching::quality(&short_trace, route, 0.0, 0);
+        assert_eq!(quality.mean_distance_meters, None);
+        assert_eq!(quality.max_distance_meters, None);
+    }
+
+    // A long trace across the map, with a point every 5m
+    snapper.router.config.extend_route = true;
+    for waypt in [WAYPT5, WAYPT2, WAYPT4] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    let original = snapper.entire_line_string(&snapper.route).unwrap();
+    let original_length = original.length(&Haversine);
+    let trace = Haversine.densify(&original, 5.0).0;
+    assert!(trace.len() > 1000);
+    let input = serde_json::to_string(&Geometry::from(&LineString::new(trace))).unwrap();
+    let quality: serde_json::Value =
+        serde_json::from_str(&matched.match_trace(input, 5.0).unwrap()).unwrap();
+    assert_eq!(quality["matched_fraction"], 1.0);
+    assert!(quality["mean_distance_meters"].as_f64().unwrap() < 1.0);
+    assert!(matched.route.waypoints.len() <= 2 * snapper.route.waypoints.len());
+    let length = quality["route_length_meters"].as_f64().unwrap();
+    assert!((length - original_length).abs() < 0.01 * original_length);
+}
+
+#[test]
+fn test_disconnected_legs() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+    snapper.router.config.extend_route = true;
+
+    // An edge far away from everything else
+    let pt1 = snapper.router.map.node(NodeID(10));
+    let far_away = new_edge_feature(
+        vec![
+            Coord {
+                x: pt1.x + 0.05,
+                y: pt1.y + 0.05,
+            },
+            Coord {
+                x: pt1.x + 0.051,
+                y: pt1.y + 0.05,
+            },
+        ],
+        1.0,
+    );
+    snapper.add_edge(far_away, 0.1).unwrap();
+    let island = Waypoint::Snapped(NodeID(snapper.router.map.nodes.len() as u32 - 1));
+
+    // A dead-end that can't be used
+    let dead_end = snapper
+        .router
+        .graph
+        .nodes()
+        .find(|n| snapper.router.graph.neighbors(*n).count() == 1)
+        .unwrap();
+    let (_, _, edge) = snapper.router.graph.edges(dead_end).next().unwrap();
+    snapper.close_edge(edge.0 .0).unwrap();
+    let dead_end = Waypoint::Snapped(dead_end);
+
+    for waypt in [WAYPT1, island, dead_end] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, island, dead_end]);
+    let feature: Feature = snapper.to_final_feature().unwrap().parse().unwrap();
+    let legs = feature
+        .property("legs")
+        .unwrap()
+        .as_array()
+        .unwrap()
+        .clone();
+    assert_eq!(legs[0]["routed"], false);
+    assert_eq!(legs[0]["disconnected"], "different_component");
+    assert_eq!(legs[1]["disconnected"], "different_component");
+
+    snapper.undo();
+    snapper.undo();
+    must_mouseover_waypt(&mut snapper, dead_end);
+    snapper.on_click();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, dead_end]);
+    let feature: Feature = snapper.to_final_feature().unwrap().parse().unwrap();
+    let legs = feature
+        .property("legs")
+        .unwrap()
+        .as_array()
+        .unwrap()
+        .clone();
+    assert_eq!(legs[0]["disconnected"], "restricted");
+    let gj: FeatureCollection = snapper.render_geojson().parse().unwrap();
+    assert_eq!(
+        gj.features
+            .iter()
+            .filter(|f| f.property("disconnected").is_some())
+            .count(),
+        1
+    );
+
+    // Optionally refuse waypoints that can't be reached
+    snapper.set_refuse_disconnected(true);
+    snapper.undo();
+    let undo_length = snapper.history.undo_length();
+    for waypt in [island, dead_end] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1]);
+    assert_eq!(snapper.history.undo_length(), undo_length);
+
+    // Including when dragging
+    must_mouseover_waypt(&mut snapper, WAYPT2);
+    snapper.on_click();
+    drag(&mut snapper, WAYPT2, island);
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, WAYPT2]);
+}
+
+#[test]
+fn test_components() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+    snapper.router.config.extend_route = true;
+
+    let pt1 = snapper.router.map.node(NodeID(10));
+    let far_away = new_edge_feature(
+        vec![
+            Coord {
+                x: pt1.x + 0.05,
+                y: pt1.y + 0.05,
+            },
+            Coord {
+                x: pt1.x + 0.051,
+                y: pt1.y + 0.05,
+            },
+        ],
+        1.0,
+    );
+    snapper.add_edge(far_away, 0.1).unwrap();
+    let num_nodes = snapper.router.map.nodes.len();
+    let island = Waypoint::Snapped(NodeID(num_nodes as u32 - 1));
+
+    // The new edge is its own small component
+    let components = &snapper.router.components;
+    assert_eq!(components.len(), num_nodes);
+    assert_eq!(components[num_nodes - 1], components[num_nodes - 2]);
+    assert_ne!(components[num_nodes - 1], components[10]);
+    assert_eq!(components[10], ComponentID(0));
+
+    // Snapping can be kept in the component of the previous waypoint
+    snapper.set_snap_within_component(true);
+    must_mouseover_waypt(&mut snapper, WAYPT1);
+    snapper.on_click();
+    optionally_mouseover_waypt(&mut snapper, island);
+    assert_ne!(snapper.mode, Mode::Hovering(island));
+    snapper.set_snap_within_component(false);
+    must_mouseover_waypt(&mut snapper, island);
+
+    // Pruning removes the island and leaves a valid graph
+    let mut map: RouteSnapperMap = bincode::deserialize(&map_bytes).unwrap();
+    map.finalize().unwrap();
+    let before = map.nodes.len();
+    let removed = map.remove_small_components(10);
+    assert_eq!(map.nodes.len(), before - removed);
+    let components = map.strongly_connected_components();
+    let mut sizes: BTreeMap<ComponentID, usize> = BTreeMap::new();
+    for id in components {
+        *sizes.entry(id).or_default() += 1;
+    }
+    assert!(sizes.values().all(|size| *size >= 10));
+    for edge in &map.edges {
+        assert!((edge.node1.0 as usize) < map.nodes.len());
+        assert!((edge.node2.0 as usize) < map.nodes.len());
+    }
+    map.finalize().unwrap();
+}
+
+#[test]
+fn test_contraction_hierarchy() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+
+    let pairs = [
+        (WAYPT1, WAYPT2),
+        (WAYPT2, WAYPT5),
+        (WAYPT4, WAYPT1),
+        (WAYPT3, WAYPT3),
+    ];
+    let expected: Vec<f64> = pairs
+        .iter()
+        .map(|(from, to)| path_cost(&snapper, *from, *to))
+        .collect();
+
+    snapper.router.ch = Some(ContractionHierarchy::new(&snapper.router.map));
+    for ((from, to), expected) in pairs.into_iter().zip(expected) {
+        // The graph used for A* only keeps one edge between two nodes, so the hierarchy may find
+        // something slightly cheaper
+        assert!(path_cost(&snapper, from, to) <= expected + 1e-6);
+    }
+}
+
+#[test]
+fn test_alternatives() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+
+    for waypt in [WAYPT1, WAYPT2] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    let original_path = snapper.route.full_path.clone();
+
+    let num = snapper.preview_alternatives(0, 2).unwrap();
+    assert!(num > 0);
+    let alternative = snapper.alternatives.as_ref().unwrap().paths[0].clone();
+    assert_ne!(alternative, original_path);
+
+    // Pinning the alternative adds shaping waypoints, but keeps the endpoints
+    snapper.pin_alternative(0).unwrap();
+    assert!(snapper.alternatives.is_none());
+    assert!(snapper.route.waypoints.len() > 2);
+    assert_eq!(snapper.route.waypoints[0], WAYPT1);
+    assert_eq!(*snapper.route.waypoints.last().unwrap(), WAYPT2);
+    let mut full_path = snapper.route.full_path.clone();
+    full_path.dedup();
+    assert_eq!(full_path, alternative);
+
+    // And it's one step to undo
+    snapper.undo();
+    assert_eq!(snapper.route.waypoints, vec![WAYPT1, WAYPT2]);
+}
+
+#[test]
+fn test_zones() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+
+    for waypt in [WAYPT1, WAYPT2] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    let original_path = snapper.route.full_path.clone();
+
+    // Block a small area around an edge in the middle of the route
+    let edges: Vec<DirectedEdge> = original_path
+        .iter()
+        .filter_map(|x| match x {
+            PathEntry::Edge(dir_edge) => Some(*dir_edge),
+            _ => None,
+        })
+        .collect();
+    let blocked = edges[edges.len() / 2];
+    let center = snapper.router.map.edge(blocked.0).geometry.0[0];
+    let size = 0.0001;
+    let polygon = Polygon::new(
+        LineString::from(vec![
+            (center.x - size, center.y - size),
+            (center.x + size, center.y - size),
+            (center.x + size, center.y + size),
+            (center.x - size, center.y + size),
+            (center.x - size, center.y - size),
+        ]),
+        Vec::new(),
+    );
+    let mut f = Feature::from(Geometry::from(&polygon));
+    f.set_property("block", true);
+    let zones = FeatureCollection::from_iter(vec![f]).to_string();
+    snapper.set_zones(zones.clone()).unwrap();
+
+    assert_ne!(snapper.route.full_path, original_path);
+    assert!(!snapper
+        .route
+        .full_path
+        .iter()
+        .any(|x| matches!(x, PathEntry::Edge(dir_edge) if dir_edge.0 == blocked.0)));
+
+    // Removing zones restores the route
+    snapper
+        .set_zones(FeatureCollection::from_iter(Vec::new()).to_string())
+        .unwrap();
+    assert_eq!(snapper.route.full_path, original_path);
+
+    // A cheap new edge through a blocked zone isn't used
+    snapper.set_zones(zones).unwrap();
+    let shortcut = vec![
+        unhash_pt(snapper.to_pt(WAYPT1)),
+        center,
+        unhash_pt(snapper.to_pt(WAYPT2)),
+    ];
+    let id = snapper
+        .add_edge(new_edge_feature(shortcut, 1.0), 1.0)
+        .unwrap();
+    let uses_shortcut = |snapper: &JsRouteSnapper| {
+        snapper
+            .route
+            .full_path
+            .iter()
+            .any(|x| matches!(x, PathEntry::Edge(dir_edge) if dir_edge.0 == EdgeID(id)))
+    };
+    assert!(!uses_shortcut(&snapper));
+
+    snapper
+        .set_zones(FeatureCollection::from_iter(Vec::new()).to_string())
+        .unwrap();
+    assert!(uses_shortcut(&snapper));
+}
+
+#[test]
+fn test_edge_costs() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+
+    for waypt in [WAYPT1, WAYPT2] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    let original_path = snapper.route.full_path.clone();
+    let uses_edge = |snapper: &JsRouteSnapper, id: EdgeID| {
+        snapper
+            .route
+            .full_path
+            .iter()
+            .any(|x| matches!(x, PathEntry::Edge(dir_edge) if dir_edge.0 == id))
+    };
+
+    let middle = original_path
+        .iter()
+        .filter_map(|x| match x {
+            PathEntry::Edge(dir_edge) => Some(*dir_edge),
+            _ => None,
+        })
+        .nth(3)
+        .unwrap();
+
+    // Closing the edge only in the opposite direction doesn't matter
+    snapper
+        .set_edge_cost(middle.0 .0, middle.1 != FORWARDS, None)
+        .unwrap();
+    assert_eq!(snapper.route.full_path, original_path);
+
+    // Closing it in the direction used changes the route
+    snapper
+        .set_edge_cost(middle.0 .0, middle.1 == FORWARDS, None)
+        .unwrap();
+    assert!(!uses_edge(&snapper, middle.0));
+
+    // So does making it very expensive
+    snapper.restore_edge(middle.0 .0).unwrap();
+    assert_eq!(snapper.route.full_path, original_path);
+    snapper
+        .set_edge_cost(middle.0 .0, middle.1 == FORWARDS, Some(1_000_000.0))
+        .unwrap();
+    assert!(!uses_edge(&snapper, middle.0));
+
+    snapper.close_edge(middle.0 .0).unwrap();
+    assert!(!uses_edge(&snapper, middle.0));
+    snapper.restore_edge(middle.0 .0).unwrap();
+    assert_eq!(snapper.route.full_path, original_path);
+
+    // Splitting an edge divides its override between the two halves
+    let longest = original_path
+        .iter()
+        .filter_map(|x| match x {
+            PathEntry::Edge(dir_edge) => Some(*dir_edge),
+            _ => None,
+        })
+        .max_by(|a, b| {
+            let length =
+                |dir_edge: &DirectedEdge| snapper.router.map.edge(dir_edge.0).length_meters;
+            length(a).total_cmp(&length(b))
+        })
+        .unwrap();
+    snapper
+        .set_edge_cost(longest.0 .0, longest.1 == FORWARDS, Some(1.0))
+        .unwrap();
+    assert!(uses_edge(&snapper, longest.0));
+    let cost = snapper.router.path_cost(&snapper.route.full_path);
+    let line = snapper
+        .router
+        .map
+        .edge(longest.0)
+        .geometry
+        .lines()
+        .max_by(|a, b| a.length(&Euclidean).total_cmp(&b.length(&Euclidean)))
+        .unwrap();
+    let on_edge = (line.start + line.end) / 2.0;
+    let off_edge = Coord {
+        x: on_edge.x + 0.001,
+        y: on_edge.y + 0.001,
+    };
+    let num_edges = snapper.router.map.edges.len();
+    snapper
+        .add_edge(new_edge_feature(vec![off_edge, on_edge], 1000.0), 1.0)
+        .unwrap();
+    assert_eq!(snapper.router.map.edges.len(), num_edges + 2);
+    assert!(uses_edge(&snapper, longest.0));
+    assert!(uses_edge(&snapper, EdgeID(num_edges as u32)));
+    assert!((snapper.router.path_cost(&snapper.route.full_path) - cost).abs() < 1e-6);
+}
+
+#[test]
+fn test_add_edge() {
+    let map_bytes = std::fs::read("../examples/southwark.bin").unwrap();
+    let mut snapper = JsRouteSnapper::new(&map_bytes).unwrap();
+
+    for waypt in [WAYPT1, WAYPT2] {
+        must_mouseover_waypt(&mut snapper, waypt);
+        snapper.on_click();
+    }
+    let num_edges = snapper.router.map.edges.len();
+
+    // A cheap shortcut directly between the two waypoints gets used
+    let pt1 = snapper.router.map.node(NodeID(10));
+    let pt2 = snapper.router.map.node(NodeID(20));
+    let shortcut = new_edge_feature(vec![pt1, pt2], 1.0);
+    let id = snapper.add_edge(shortcut, 1.0).unwrap();
+    assert_eq!(id as usize, num_edges);
+    assert_eq!(snapper.router.map.edges.len(), num_edges + 1);
+    assert_eq!(
+        snapper
+            .route
+            .full_path
+            .iter()
+            .filter(|x| matches!(x, PathEntry::Edge(_)))
+            .count(),
+        1
+    );
+
+    // An edge ending in the middle of an existing edge splits it
+    let (node1, node2) = {
+        let e = snapper.router.map.edge(EdgeID(5));
+        (e.node1, e.node2)
+    };
+    let line = snapper
+        .router
+        .map
+        .edge(EdgeID(5))
+        .geometry
+        .lines()
+        .next()
+        .unwrap();
+    let on_edge = (line.start + line.end) / 2.0;
+    let off_edge = Coord {
+        x: on_edge.x + 0.001,
+        y: on_edge.y + 0.001,
+    };
+    snapper
+        .add_edge(new_edge_feature(vec![off_edge, on_edge], 10.0), 1.0)
+        .unwrap();
+    assert_eq!(snapper.router.map.edges.len(), num_edges + 3);
+    let split_node = snapper.router.map.edge(EdgeID(5)).node2;
+    assert_ne!(split_node, node2);
+    assert!(snapper.router.graph.contains_edge(split_node, node2));
+    assert!(!snapper.router.graph.contains_edge(node1, node2));
+    assert_eq!(snapper.snap_to_nodes.size(), snapper.router.map.nodes.len());
+    // The spatial index was updated to match the split
+    let rebuilt = snapper.router.map.index_edge_segments();
+    assert_eq!(snapper.edge_segments.size(), rebuilt.size());
+    assert!(rebuilt.iter().all(|x| snapper.edge_segments.contains(x)));
+
+    // The same edits apply to a fresh copy of the graph
+    let patch = snapper.export_graph_patch();
+    let mut fresh = JsRouteSnapper::new(&map_bytes).unwrap();
+    fresh.apply_graph_patch(patch).unwrap();
+    assert_eq!(fresh.router.map.nodes.len(), snapper.router.map.nodes.len());
+    // Allow for floating point error from the JSON round-trip
+    for (pt1, pt2) in fresh.router.map.nodes.iter().zip(&snapper.router.map.nodes) {
+        assert!((pt1.x - pt2.x).abs() < 1e-9 && (pt1.y - pt2.y).abs() < 1e-9);
+    }
+    assert_eq!(fresh.router.map.edges.len(), snapper.router.map.edges.len());
+    assert_eq!(
+        fresh.router.graph.edge_count(),
+        snapper.router.graph.edge_count()
+    );
+
+    // Costs must be non-negative, and a bad patch changes nothing
+    let mut patch: GraphPatch = serde_json::from_str(&snapper.export_graph_patch()).unwrap();
+    patch.new_edges[1].backward_cost = Some(-1.0);
+    let num_edges = fresh.router.map.edges.len();
+    let (nodes, edge_segments) = (&mut fresh.snap_to_nodes, &mut fresh.edge_segments);
+    assert!(fresh
+        .router
+        .map
+        .add_edge(&patch.new_edges[1], 1.0, nodes, edge_segments)
+        .is_err());
+    assert!(patch
+        .apply(&mut fresh.router.map, nodes, edge_segments)
+        .is_err());
+    assert_eq!(fresh.router.map.edges.len(), num_edges);
+}
+
+fn new_edge_feature(pts: Vec<Coord>, cost: f64) -> String {
+    let mut f = Feature::from(geojson::Geometry::from(&LineString::new(pts)));
+    f.set_property("forward_cost", cost);
+    f.set_property("backward_cost", cost);
+    serde_json::to_string(&f).unwrap()
+}
+
 // Simulate the mouse being somewhere
 fn optionally_mouseover_waypt(snapper: &mut JsRouteSnapper, waypt: Waypoint) {
     let pt = unhash_pt(snapper.to_pt(waypt));
@@ -225,3 +1993,26 @@ fn drag(snapper: &mut JsRouteSnapper, from: Waypoint, to: Waypoint) {
     optionally_mouseover_waypt(snapper, to);
     snapper.on_mouse_up();
 }
+
+fn path_cost(snapper: &JsRouteSnapper, from: Waypoint, to: Waypoint) -> f64 {
+    let (Waypoint::Snapped(node1), Waypoint::Snapped(node2)) = (from, to) else {
+        panic!("path_cost needs snapped waypoints");
+    };
+    let entries = snapper.router.pathfind(node1, node2, &Vec::new()).unwrap();
+    let mut cost = 0.0;
+    for (idx, entry) in entries.iter().enumerate() {
+        if let PathEntry::Edge(dir_edge) = entry {
+            // Make sure the path is contiguous
+            let edge = snapper.router.map.edge(dir_edge.0);
+            let (start, end, edge_cost) = if dir_edge.1 == FORWARDS {
+                (edge.node1, edge.node2, edge.forward_cost)
+            } else {
+                (edge.node2, edge.node1, edge.backward_cost)
+            };
+            assert_eq!(entries[idx - 1], PathEntry::SnappedPoint(start));
+            assert_eq!(entries[idx + 1], PathEntry::SnappedPoint(end));
+            cost += edge_cost.unwrap();
+        }
+    }
+    cost
+}
@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+
+use geo::bool_ops::{BooleanOps, FillRule, OpType};
+use geo::line_intersection::line_intersection;
+use geo::orient::{Direction, Orient};
+use geo::{ChamberlainDuquetteArea, Line, LineString, MultiPolygon, Polygon};
+use rstar::primitives::{GeomWithData, Rectangle};
+use rstar::{RTree, AABB};
+
+use crate::{hash_pt, HashedPoint};
+
+// Rings enclosing less than this are treated as having no area
+const MIN_AREA_SQ_METERS: f64 = 1.0;
+
+/// Something that makes an area ring invalid as a polygon
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Problem {
+    /// Two parts of the ring cross or touch
+    SelfIntersection,
+    /// The ring follows the same segment more than once, such as a spike along a dead-end street
+    RepeatedSegment,
+    /// The whole ring encloses nothing
+    ZeroArea,
+}
+
+impl Problem {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Problem::SelfIntersection => "self_intersection",
+            Problem::RepeatedSegment => "repeated_segment",
+            Problem::ZeroArea => "zero_area",
+        }
+    }
+}
+
+/// Checks one closed ring, returning every problem along with the segments causing it. For
+/// `ZeroArea`, that's the whole ring.
+pub fn find_problems(ring: &LineString) -> Vec<(Problem, Vec<Line>)> {
+    let mut problems = Vec::new();
+    let ring = dedupe(ring);
+    if Polygon::new(ring.clone(), Vec::new()).chamberlain_duquette_unsigned_area()
+        < MIN_AREA_SQ_METERS
+    {
+        problems.push((Problem::ZeroArea, ring.lines().collect()));
+        return problems;
+    }
+
+    let lines: Vec<Line> = ring.lines().collect();
+    let repeated = repeated_segments(&lines);
+    if !repeated.is_empty() {
+        problems.push((
+            Problem::RepeatedSegment,
+            repeated.iter().map(|idx| lines[*idx]).collect(),
+        ));
+    }
+
+    // Spikes make the segments on either side touch, but they're already reported, so look for
+    // crossings without them
+    let lines: Vec<Line> = remove_spikes(&ring).lines().collect();
+    let crossing = crossing_segments(&lines);
+    if !crossing.is_empty() {
+        problems.push((
+            Problem::SelfIntersection,
+            crossing.into_iter().map(|idx| lines[idx]).collect(),
+        ));
+    }
+
+    problems
+}
+
+/// Removes spikes, where the ring goes somewhere and immediately doubles back, including
+/// along several segments. The result may have too few points to be a ring.
+pub fn remove_spikes(ring: &LineString) -> LineString {
+    let mut pts = dedupe(ring).0;
+    // Work with the open ring
+    pts.pop();
+    loop {
+        let len = pts.len();
+        if len < 3 {
+            break;
+        }
+        let Some(idx) = (0..len)
+            .find(|idx| hash_pt(pts[(idx + len - 1) % len]) == hash_pt(pts[(idx + 1) % len]))
+        else {
+            break;
+        };
+        // Remove the tip of the spike and the repeated point after it
+        let next = (idx + 1) % len;
+        pts.remove(idx.max(next));
+        pts.remove(idx.min(next));
+    }
+    let mut result = LineString::new(pts);
+    result.close();
+    result
+}
+
+/// Resolves any remaining self-intersections and overlaps between parts, and drops anything with
+/// no area.
+pub fn make_valid(polygons: Vec<Polygon>) -> Vec<Polygon> {
+    let oriented = MultiPolygon(
+        polygons
+            .into_iter()
+            .map(|p| p.orient(Direction::Default))
+            .collect(),
+    );
+    oriented
+        .boolean_op_with_fill_rule(
+            &MultiPolygon::new(Vec::new()),
+            OpType::Union,
+            FillRule::NonZero,
+        )
+        .0
+}
+
+// Returns the indices of segments used more than once, in either direction
+fn repeated_segments(lines: &[Line]) -> Vec<usize> {
+    let mut uses: BTreeMap<(HashedPoint, HashedPoint), Vec<usize>> = BTreeMap::new();
+    for (idx, line) in lines.iter().enumerate() {
+        uses.entry(segment_key(line)).or_default().push(idx);
+    }
+    let mut repeated: Vec<usize> = uses
+        .into_values()
+        .filter(|indices| indices.len() > 1)
+        .flatten()
+        .collect();
+    repeated.sort();
+    repeated
+}
+
+// Returns the indices of segments touching any other segment besides their neighbors. Repeated
+// segments are skipped.
+fn crossing_segments(lines: &[Line]) -> Vec<usize> {
+    let repeated = repeated_segments(lines);
+    let rtree = RTree::bulk_load(
+        lines
+            .iter()
+            .enumerate()
+            .map(|(idx, line)| {
+                GeomWithData::new(
+                    Rectangle::<[f64; 2]>::from_corners(line.start.into(), line.end.into()),
+                    idx,
+                )
+            })
+            .collect(),
+    );
+    let mut crossing = Vec::new();
+    for (idx1, line1) in lines.iter().enumerate() {
+        let envelope = AABB::from_corners(line1.start.into(), line1.end.into());
+        for obj in rtree.locate_in_envelope_intersecting(&envelope) {
+            let idx2 = obj.data;
+            if idx2 <= idx1
+                || are_neighbors(idx1, idx2, lines.len())
+                || repeated.contains(&idx1)
+                || repeated.contains(&idx2)
+            {
+                continue;
+            }
+            if line_intersection(*line1, lines[idx2]).is_some() {
+                crossing.push(idx1);
+                crossing.push(idx2);
+            }
+        }
+    }
+    crossing.sort();
+    crossing.dedup();
+    crossing
+}
+
+// Removes adjacent duplicate points
+fn dedupe(ring: &LineString) -> LineString {
+    let mut pts = ring.0.clone();
+    pts.dedup_by_key(|pt| hash_pt(*pt));
+    LineString::new(pts)
+}
+
+// The same for both directions
+fn segment_key(line: &Line) -> (HashedPoint, HashedPoint) {
+    let pt1 = hash_pt(line.start);
+    let pt2 = hash_pt(line.end);
+    (pt1.min(pt2), pt1.max(pt2))
+}
+
+// Adjacent segments always touch, including the first and last
+fn are_neighbors(idx1: usize, idx2: usize, len: usize) -> bool {
+    idx1 + 1 == idx2 || (idx1 == 0 && idx2 == len - 1)
+}